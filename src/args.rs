@@ -1,6 +1,61 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use bevy::{
+    app::TaskPoolOptions,
+    ecs::resource::Resource,
+    math::Rect,
+    render::{
+        settings::{PowerPreference, WgpuFeatures, WgpuSettings},
+        texture::ImagePlugin,
+        view::Msaa,
+    },
+    window::PresentMode,
+    winit::{UpdateMode, WinitSettings},
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Environment variable naming the settings file.
+pub const CONFIG_ENV: &str = "METAWRITE_CONFIG";
+
+const USAGE: &str = "\
+Usage: metawrite [OPTIONS] [PROJECT]
+
+Opens PROJECT, a .metawrite file, which is saved back to on Ctrl+S. Without one, the project is
+kept in the app's key-value store.
+
+Options:
+  --config <FILE>      Settings file, as JSON [default: $METAWRITE_CONFIG, or
+                       $XDG_CONFIG_HOME/metawrite/tuning.json, or ~/.config/metawrite/tuning.json]
+  --print-config       Print the effective settings and exit. The output can be saved as the
+                       settings file.
+  --export <FILE>      Export PROJECT to FILE and exit, without opening a window. The format
+                       follows the extension: .pdf for all pages, .svg or .png for the first.
+  --dpi <DPI>          Resolution of PNG exports, taking a canvas unit as 1/96 inch: 96 gives a
+                       pixel per canvas unit, 192 gives two [default: 96]
+  --bounds <X0,Y0,X1,Y1>
+                       Part of the canvas in PNG exports [default: all strokes]
+  --aa <MODE>          Anti-aliasing: none, msaa2, msaa4, msaa8, fxaa or smaa
+  --fps-limit <FPS>    Most frames per second, or none
+  --power <PREF>       GPU power preference: full, save or auto
+  --ink <SOURCE>       Touches which draw: any, stylus or auto
+  --stabilizer <PX>    How far ink trails the pointer to smooth strokes, or 0 for off
+  --simplify <PX>      Most a finished stroke may move when dropping its points, or 0 for off
+  --host <ADDR>        Share the canvas with peers joining at ADDR, such as 0.0.0.0:4617
+  --join <ADDR>        Share the canvas of the host at ADDR, such as 192.168.1.2:4617
+  --[no-]vsync, --[no-]low-power, --[no-]pipelined, --[no-]multithreading, --[no-]diagnostic,
+  --[no-]async-assets, --[no-]texture-compression, --[no-]frustum-culling, --[no-]smooth-scaling,
+  --[no-]palm-rejection
+                       Turn a setting on or off
+  -h, --help           Print this help
+
+Settings given here override those of the settings file.
+";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
 pub struct Tuning {
     /// Anti-aliasing level (None = off, Some(x) = samples count).
     #[serde(default)]
@@ -34,6 +89,19 @@ pub struct Tuning {
     pub frustum_culling: bool,
     #[cfg_attr(not(target_arch = "wasm32"), serde(default = "default_true"))]
     pub smooth_scaling: bool,
+    /// Telling palms, fingers and styluses apart on touch screens.
+    #[serde(default)]
+    pub palm_rejection: PalmRejection,
+    /// GPU to prefer where there are several.
+    #[serde(default)]
+    pub power: PowerPref,
+    /// String length of the stroke stabilizer, in logical pixels. Ink trails the pointer by this
+    /// much, hiding jitter shorter than it (0 = off).
+    #[serde(default)]
+    pub stabilizer: f32,
+    /// Finished strokes drop points which move them less than this, in logical pixels (0 = off).
+    #[serde(default = "default_simplify")]
+    pub simplify: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -45,7 +113,39 @@ pub enum AAMode {
     Msaa4,
     Msaa8,
     Fxaa,
-    Taa,
+    /// Called `Taa` in settings files of earlier versions.
+    #[serde(alias = "Taa")]
+    Smaa,
+}
+
+/// How touches are told apart. Most platforms report neither contact size nor tool type, so a
+/// stylus is known by its tilt, and palms by their force and the order they land in.
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct PalmRejection {
+    /// Reject touches which look like a palm.
+    pub enabled: bool,
+    /// Touches pressing harder than this (0 to 1) are palms, which press a wide area.
+    pub max_force: Option<f32>,
+    /// Touches landing this many seconds before a stylus are palms resting before writing.
+    pub window: f32,
+    /// Which touches draw. Others pan the canvas.
+    ///
+    /// Only iOS tells a stylus apart before one is used, as only an Apple Pencil reports its tilt.
+    /// Elsewhere [`InkSource::Stylus`] lets any touch draw until a stylus is seen, like
+    /// [`InkSource::Auto`], so touches can draw at all.
+    pub ink: InkSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum InkSource {
+    /// Any touch.
+    Any,
+    /// A stylus only, where one can be told apart. See [`PalmRejection::ink`].
+    Stylus,
+    /// Any touch until a stylus is used, then a stylus only.
+    #[default]
+    Auto,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -60,6 +160,10 @@ fn default_true() -> bool {
     true
 }
 
+fn default_simplify() -> f32 {
+    0.5
+}
+
 impl Default for Tuning {
     fn default() -> Self {
         Self {
@@ -74,6 +178,413 @@ impl Default for Tuning {
             texture_compression: false,
             frustum_culling: true,
             smooth_scaling: false,
+            palm_rejection: Default::default(),
+            power: Default::default(),
+            stabilizer: 0.0,
+            simplify: default_simplify(),
+        }
+    }
+}
+
+impl Tuning {
+    /// Where settings are read from: `$METAWRITE_CONFIG`, or `metawrite/tuning.json` in the user
+    /// config directory.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV) {
+            return Some(path.into());
+        }
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|x| Path::new(&x).join(".config")))?;
+        Some(config.join("metawrite").join("tuning.json"))
+    }
+
+    /// Read settings from `path`. Fields left out take their defaults.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        serde_json::from_slice(&data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Settings from `path`, or defaults if there is no such file.
+    pub fn from_config(path: Option<&Path>) -> Self {
+        match path.filter(|x| x.exists()) {
+            Some(path) => Self::load(path).unwrap_or_else(|e| {
+                // Logging is not set up yet.
+                eprintln!("Failed to read settings, using defaults: {e}");
+                Self::default()
+            }),
+            None => Self::default(),
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    /// MSAA of the camera. Other anti-aliasing is a post-process, which needs MSAA off.
+    pub fn msaa(&self) -> Msaa {
+        match self.anti_aliasing {
+            AAMode::Msaa2 => Msaa::Sample2,
+            AAMode::Msaa4 => Msaa::Sample4,
+            AAMode::Msaa8 => Msaa::Sample8,
+            AAMode::None | AAMode::Fxaa | AAMode::Smaa => Msaa::Off,
+        }
+    }
+
+    /// How often the app updates. The canvas only changes on input, so it always waits for it,
+    /// and in low power mode device events like raw mouse motion do not wake it either.
+    pub fn winit_settings(&self) -> WinitSettings {
+        if self.low_power {
+            WinitSettings {
+                focused_mode: UpdateMode::reactive_low_power(Duration::from_secs(5)),
+                unfocused_mode: UpdateMode::reactive_low_power(Duration::from_secs(60)),
+            }
+        } else {
+            WinitSettings::desktop_app()
+        }
+    }
+
+    pub fn wgpu_settings(&self) -> WgpuSettings {
+        let mut settings = WgpuSettings::default();
+        match self.power {
+            PowerPref::Full => settings.power_preference = PowerPreference::HighPerformance,
+            PowerPref::Save => settings.power_preference = PowerPreference::LowPower,
+            PowerPref::Auto if self.low_power => {
+                settings.power_preference = PowerPreference::LowPower
+            }
+            PowerPref::Auto => {}
+        }
+        if !self.texture_compression {
+            settings.disabled_features = Some(
+                WgpuFeatures::TEXTURE_COMPRESSION_BC
+                    | WgpuFeatures::TEXTURE_COMPRESSION_ETC2
+                    | WgpuFeatures::TEXTURE_COMPRESSION_ASTC
+                    | WgpuFeatures::TEXTURE_COMPRESSION_ASTC_HDR,
+            );
+        }
+        settings
+    }
+
+    pub fn task_pool_options(&self) -> TaskPoolOptions {
+        if self.multithreading {
+            TaskPoolOptions::default()
+        } else {
+            TaskPoolOptions::with_num_threads(1)
+        }
+    }
+
+    pub fn image_plugin(&self) -> ImagePlugin {
+        if self.smooth_scaling {
+            ImagePlugin::default_linear()
+        } else {
+            ImagePlugin::default_nearest()
+        }
+    }
+}
+
+impl Default for PalmRejection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_force: None,
+            window: 0.5,
+            ink: InkSource::default(),
+        }
+    }
+}
+
+impl FromStr for AAMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(AAMode::None),
+            "msaa2" => Ok(AAMode::Msaa2),
+            "msaa4" => Ok(AAMode::Msaa4),
+            "msaa8" => Ok(AAMode::Msaa8),
+            "fxaa" => Ok(AAMode::Fxaa),
+            "smaa" => Ok(AAMode::Smaa),
+            _ => Err(format!("unknown anti-aliasing {s:?}")),
+        }
+    }
+}
+
+impl FromStr for PowerPref {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" => Ok(PowerPref::Full),
+            "save" => Ok(PowerPref::Save),
+            "auto" => Ok(PowerPref::Auto),
+            _ => Err(format!("unknown power preference {s:?}")),
         }
     }
 }
+
+impl FromStr for InkSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "any" => Ok(InkSource::Any),
+            "stylus" => Ok(InkSource::Stylus),
+            "auto" => Ok(InkSource::Auto),
+            _ => Err(format!("unknown ink source {s:?}")),
+        }
+    }
+}
+
+/// A setting turned on by `--name` and off by `--no-name`.
+type Switch = fn(&mut Tuning) -> &mut bool;
+
+const SWITCHES: &[(&str, Switch)] = &[
+    ("vsync", |x| &mut x.vsync),
+    ("low-power", |x| &mut x.low_power),
+    ("pipelined", |x| &mut x.pipelined),
+    ("multithreading", |x| &mut x.multithreading),
+    ("diagnostic", |x| &mut x.diagnostic),
+    ("async-assets", |x| &mut x.async_assets),
+    ("texture-compression", |x| &mut x.texture_compression),
+    ("frustum-culling", |x| &mut x.frustum_culling),
+    ("smooth-scaling", |x| &mut x.smooth_scaling),
+    ("palm-rejection", |x| &mut x.palm_rejection.enabled),
+];
+
+/// A rectangle given by two corners, as `x0,y0,x1,y1`.
+fn parse_bounds(s: &str) -> Result<Rect, String> {
+    let corners: Vec<f32> = s
+        .split(',')
+        .map(|x| x.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    match corners[..] {
+        [x0, y0, x1, y1] if x0 != x1 && y0 != y1 => Ok(Rect::new(x0, y0, x1, y1)),
+        _ => Err(format!(
+            "expected two opposite corners as x0,y0,x1,y1, got {s:?}"
+        )),
+    }
+}
+
+/// How the canvas is shared with peers.
+#[derive(Debug, Clone, Resource)]
+pub enum Collab {
+    /// Listen for peers at an address.
+    Host(String),
+    /// Connect to the host at an address.
+    Join(String),
+}
+
+/// A setting given on the command line.
+#[derive(Debug, Clone)]
+enum Override {
+    AntiAliasing(AAMode),
+    FpsLimit(Option<f32>),
+    Power(PowerPref),
+    Ink(InkSource),
+    Stabilizer(f32),
+    Simplify(f32),
+    Switch(Switch, bool),
+}
+
+/// Options from the command line.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Project file to open and save to.
+    pub project: Option<PathBuf>,
+    /// Settings file to read instead of [`Tuning::path`].
+    pub config: Option<PathBuf>,
+    /// Print the effective settings and exit.
+    pub print_config: bool,
+    /// File to export the project to instead of opening it.
+    pub export: Option<PathBuf>,
+    /// Resolution of raster exports.
+    pub dpi: Option<f32>,
+    /// Part of the canvas in raster exports.
+    pub bounds: Option<Rect>,
+    /// Peers to share the canvas with.
+    pub collab: Option<Collab>,
+    overrides: Vec<Override>,
+}
+
+impl Args {
+    /// Parse the command line, exiting on `--help` or a bad option.
+    pub fn from_env() -> Self {
+        match Self::parse(lexopt::Parser::from_env()) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("metawrite: {e}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    fn parse(mut parser: lexopt::Parser) -> Result<Self, lexopt::Error> {
+        use lexopt::prelude::*;
+
+        let mut args = Args::default();
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Short('h') | Long("help") => {
+                    print!("{USAGE}");
+                    std::process::exit(0);
+                }
+                Long("config") => args.config = Some(parser.value()?.into()),
+                Long("print-config") => args.print_config = true,
+                Long("export") => args.export = Some(parser.value()?.into()),
+                Long("dpi") => args.dpi = Some(parser.value()?.parse()?),
+                Long("bounds") => args.bounds = Some(parser.value()?.parse_with(parse_bounds)?),
+                Long("host") => args.collab = Some(Collab::Host(parser.value()?.string()?)),
+                Long("join") => args.collab = Some(Collab::Join(parser.value()?.string()?)),
+                Long("aa") => {
+                    let aa = parser.value()?.parse()?;
+                    args.overrides.push(Override::AntiAliasing(aa));
+                }
+                Long("fps-limit") => {
+                    let value = parser.value()?;
+                    let limit = if value == "none" {
+                        None
+                    } else {
+                        Some(value.parse()?)
+                    };
+                    args.overrides.push(Override::FpsLimit(limit));
+                }
+                Long("power") => {
+                    let power = parser.value()?.parse()?;
+                    args.overrides.push(Override::Power(power));
+                }
+                Long("ink") => {
+                    let ink = parser.value()?.parse()?;
+                    args.overrides.push(Override::Ink(ink));
+                }
+                Long("stabilizer") => {
+                    let length = parser.value()?.parse()?;
+                    args.overrides.push(Override::Stabilizer(length));
+                }
+                Long("simplify") => {
+                    let tolerance = parser.value()?.parse()?;
+                    args.overrides.push(Override::Simplify(tolerance));
+                }
+                // Taken by bevy_mod_debugdump.
+                Long(name) if name.starts_with("dump-") => {
+                    parser.value()?;
+                }
+                Long(name) => {
+                    let (on, switch) = match name.strip_prefix("no-") {
+                        Some(switch) => (false, switch),
+                        None => (true, name),
+                    };
+                    let Some((_, field)) = SWITCHES.iter().find(|x| x.0 == switch) else {
+                        return Err(arg.unexpected());
+                    };
+                    args.overrides.push(Override::Switch(*field, on));
+                }
+                Value(path) if args.project.is_none() => args.project = Some(path.into()),
+                _ => return Err(arg.unexpected()),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Apply settings given on the command line over `tuning`.
+    pub fn apply(&self, tuning: &mut Tuning) {
+        for x in &self.overrides {
+            match x {
+                Override::AntiAliasing(aa) => tuning.anti_aliasing = aa.clone(),
+                Override::FpsLimit(limit) => tuning.fps_limit = *limit,
+                Override::Power(power) => tuning.power = power.clone(),
+                Override::Ink(ink) => tuning.palm_rejection.ink = *ink,
+                Override::Stabilizer(length) => tuning.stabilizer = length.max(0.0),
+                Override::Simplify(tolerance) => tuning.simplify = tolerance.max(0.0),
+                Override::Switch(field, on) => *field(tuning) = *on,
+            }
+        }
+    }
+
+    /// Print where settings and the project come from to stderr, and the settings to stdout.
+    pub fn print_config(&self, tuning: &Tuning, config: Option<&Path>) {
+        match config {
+            Some(path) if path.exists() => eprintln!("Settings file: {}", path.display()),
+            Some(path) => eprintln!("Settings file: {} (not found)", path.display()),
+            None => eprintln!("Settings file: none"),
+        }
+        match &self.project {
+            Some(path) => eprintln!("Project: {}", path.display()),
+            None => eprintln!("Project: key-value store"),
+        }
+        match serde_json::to_string_pretty(tuning) {
+            Ok(json) => println!("{json}"),
+            Err(e) => eprintln!("Failed to print settings: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, lexopt::Error> {
+        Args::parse(lexopt::Parser::from_args(args))
+    }
+
+    #[test]
+    fn parses_exports() {
+        let args = parse(&["notes.metawrite", "--export", "out.png", "--dpi", "192.5"]).unwrap();
+        assert_eq!(args.project, Some(PathBuf::from("notes.metawrite")));
+        assert_eq!(args.export, Some(PathBuf::from("out.png")));
+        assert_eq!(args.dpi, Some(192.5));
+        let args = parse(&["--bounds", "10,20, 0,0"]).unwrap();
+        assert_eq!(args.bounds, Some(Rect::new(0.0, 0.0, 10.0, 20.0)));
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert!(parse(&["--dpi"]).is_err());
+        assert!(parse(&["--dpi", "high"]).is_err());
+        assert!(parse(&["--bounds", "0,0,0,10"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["--no-frobnicate"]).is_err());
+        assert!(parse(&["--aa", "taa"]).is_err());
+        assert!(parse(&["one.metawrite", "two.metawrite"]).is_err());
+    }
+
+    #[test]
+    fn reads_earlier_settings() {
+        let tuning: Tuning =
+            serde_json::from_str(r#"{"anti_aliasing": "Taa", "vsync": false}"#).unwrap();
+        assert!(matches!(tuning.anti_aliasing, AAMode::Smaa));
+        assert!(!tuning.vsync);
+    }
+
+    #[test]
+    fn overrides_settings_in_order() {
+        let args = parse(&[
+            "--no-vsync",
+            "--aa",
+            "fxaa",
+            "--stabilizer",
+            "-3",
+            "--fps-limit",
+            "30",
+            "--fps-limit",
+            "none",
+            "--ink",
+            "Stylus",
+        ])
+        .unwrap();
+        let mut tuning = Tuning {
+            vsync: true,
+            ..Default::default()
+        };
+        args.apply(&mut tuning);
+        assert!(!tuning.vsync);
+        assert!(matches!(tuning.anti_aliasing, AAMode::Fxaa));
+        assert_eq!(tuning.stabilizer, 0.0);
+        assert_eq!(tuning.fps_limit, None);
+        assert_eq!(tuning.palm_rejection.ink, InkSource::Stylus);
+    }
+}
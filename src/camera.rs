@@ -0,0 +1,210 @@
+//! Pan and zoom of the canvas.
+//!
+//! The camera moves over the canvas rather than the strokes, so input handlers keep finding canvas
+//! positions with `viewport_to_world_2d`.
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+
+use crate::{
+    MousePosition, TouchMove,
+    args::PalmRejection,
+    input::{Contact, TouchClasses},
+    ui::OverlayState,
+};
+
+/// Zoom factor of one wheel notch.
+const WHEEL_ZOOM: f32 = 1.1;
+/// Logical pixels scrolled by one wheel notch, for touchpads which scroll by pixel.
+const WHEEL_PIXELS: f32 = 40.0;
+/// Closest and farthest zoom, as canvas units per logical pixel.
+const MIN_SCALE: f32 = 1.0 / 16.0;
+const MAX_SCALE: f32 = 16.0;
+
+/// How finely strokes are tessellated, following the zoom. Kept to powers of two so strokes are
+/// only tessellated again when zoom changes a lot.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Resource)]
+pub struct MeshDetail(pub f32);
+
+impl Default for MeshDetail {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct PanZoom {
+    /// Cursor position in the last frame of a mouse drag.
+    drag: Option<Vec2>,
+    /// Fingers, their midpoint and the distance between them in the last frame of a touch pan.
+    pinch: Option<(usize, Vec2, f32)>,
+}
+
+pub struct PanZoomPlugin;
+
+impl Plugin for PanZoomPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PanZoom>()
+            .init_resource::<MeshDetail>()
+            .register_type::<MeshDetail>()
+            .add_systems(
+                Update,
+                (
+                    (mouse_pan, wheel_zoom, touch_pan_zoom).run_if(in_state(OverlayState::Normal)),
+                    update_mesh_detail,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Whether the space bar turns left drags into pans.
+pub fn space_pan(keyboard: &ButtonInput<KeyCode>) -> bool {
+    keyboard.pressed(KeyCode::Space)
+}
+
+/// Zoom the view by `factor` keeping the canvas under `anchor` in place, then move it along with
+/// the pointer by `pan`. Both are in viewport coordinates.
+fn move_view(
+    camera: &Camera,
+    transform: &mut Transform,
+    projection: &mut Projection,
+    anchor: Vec2,
+    factor: f32,
+    pan: Vec2,
+) {
+    let Projection::Orthographic(ortho) = projection else {
+        return;
+    };
+    let Some(size) = camera.logical_viewport_size() else {
+        return;
+    };
+    let flip = |x: Vec2| vec2(x.x, -x.y);
+    let offset = flip(anchor - size / 2.0);
+    let scale = (ortho.scale * factor).clamp(MIN_SCALE, MAX_SCALE);
+    let anchored = transform.translation.truncate() + offset * ortho.scale;
+    let translation = anchored - offset * scale - flip(pan) * scale;
+    transform.translation = translation.extend(transform.translation.z);
+    ortho.scale = scale;
+}
+
+/// Pan with the middle button, or with the left one while space is held.
+fn mouse_pan(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_position: Res<MousePosition>,
+    camera: Single<(&Camera, &mut Transform, &mut Projection)>,
+    mut pan_zoom: ResMut<PanZoom>,
+) {
+    let panning = mouse.pressed(MouseButton::Middle)
+        || (space_pan(&keyboard) && mouse.pressed(MouseButton::Left));
+    let Some(position) = mouse_position.0.filter(|_| panning) else {
+        pan_zoom.drag = None;
+        return;
+    };
+    if let Some(last) = pan_zoom.drag.replace(position) {
+        let (camera, mut transform, mut projection) = camera.into_inner();
+        move_view(
+            camera,
+            &mut transform,
+            &mut projection,
+            position,
+            1.0,
+            position - last,
+        );
+    }
+}
+
+/// Zoom around the cursor with Ctrl+wheel.
+fn wheel_zoom(
+    mut wheel_events: EventReader<MouseWheel>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_position: Res<MousePosition>,
+    camera: Single<(&Camera, &mut Transform, &mut Projection)>,
+) {
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let (camera, mut transform, mut projection) = camera.into_inner();
+    for event in wheel_events.read() {
+        if !ctrl {
+            continue;
+        }
+        let notches = match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / WHEEL_PIXELS,
+        };
+        let Some(anchor) = mouse_position
+            .0
+            .or_else(|| camera.logical_viewport_size().map(|x| x / 2.0))
+        else {
+            continue;
+        };
+        move_view(
+            camera,
+            &mut transform,
+            &mut projection,
+            anchor,
+            WHEEL_ZOOM.powf(-notches),
+            Vec2::ZERO,
+        );
+    }
+}
+
+/// Pan and pinch to zoom with two fingers which are not drawing, or pan with one when fingers do
+/// not draw at all.
+fn touch_pan_zoom(
+    touches: Res<Touches>,
+    touch_state: Res<TouchMove>,
+    classes: Res<TouchClasses>,
+    policy: Res<PalmRejection>,
+    camera: Single<(&Camera, &mut Transform, &mut Projection)>,
+    mut pan_zoom: ResMut<PanZoom>,
+) {
+    let fingers: Vec<Vec2> = touches
+        .iter()
+        .filter(|x| {
+            !touch_state.strokes.contains_key(&x.id()) && classes.contact(x.id()) == Contact::Finger
+        })
+        .map(|x| x.position())
+        .collect();
+    let (middle, distance) = match fingers[..] {
+        [a, b] => ((a + b) / 2.0, a.distance(b)),
+        [a] if !classes.fingers_ink(&policy) => (a, 0.0),
+        _ => {
+            pan_zoom.pinch = None;
+            return;
+        }
+    };
+    // The view jumps if fingers are added or lifted, so start over.
+    if let Some((count, last_middle, last_distance)) =
+        pan_zoom.pinch.replace((fingers.len(), middle, distance))
+        && count == fingers.len()
+    {
+        let factor = if distance > 0.0 {
+            last_distance / distance
+        } else {
+            1.0
+        };
+        let (camera, mut transform, mut projection) = camera.into_inner();
+        move_view(
+            camera,
+            &mut transform,
+            &mut projection,
+            last_middle,
+            factor,
+            middle - last_middle,
+        );
+    }
+}
+
+fn update_mesh_detail(
+    projection: Query<&Projection, Changed<Projection>>,
+    mut detail: ResMut<MeshDetail>,
+) {
+    let Ok(Projection::Orthographic(ortho)) = projection.single() else {
+        return;
+    };
+    detail.set_if_neq(MeshDetail((1.0 / ortho.scale).log2().round().exp2()));
+}
@@ -0,0 +1,248 @@
+//! Copy, cut and paste of selected strokes.
+//!
+//! Copied strokes are kept as a [`Canvas`], serialized as JSON like a project. The system
+//! clipboard gets them as an SVG document for other apps, with the JSON in its metadata, so
+//! strokes pasted back keep their brushes, even across windows. Without a system clipboard, they
+//! are still kept for pasting in this window, on any page.
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CurrentCurveMarker, Curve, MousePosition, Tool,
+    export::{canvas_bounds, svg::canvas_svg},
+    format::FORMAT_VERSION,
+    history::{Edit, RecordEdit, StrokeRecord},
+    merge::{Id, Stamp, now_millis},
+    selection::{Selection, SelectionEvent, update_selection},
+    spawn_curve,
+    storage::Canvas,
+    ui::OverlayEvent,
+};
+
+/// Id of the metadata element holding copied strokes in SVG.
+const METADATA_ID: &str = "metawrite-clip";
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum ClipboardEvent {
+    /// Copy the selected strokes.
+    Copy,
+    /// Copy the selected strokes, then remove them.
+    Cut,
+    /// Add copied strokes centered on the pointer, or where they were copied from without one.
+    Paste,
+}
+
+/// Copied strokes, serialized as on the clipboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Clip {
+    /// Format version of the strokes, which are only read back by the same one.
+    version: u16,
+    canvas: Canvas,
+}
+
+impl Clip {
+    fn to_json(&self) -> Option<String> {
+        serde_json::to_string(self)
+            .inspect_err(|e| warn!("Cannot serialize clip: {e}"))
+            .ok()
+    }
+
+    fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str::<Self>(json)
+            .ok()
+            .filter(|x| x.version == FORMAT_VERSION)
+    }
+
+    /// SVG document of the strokes, holding them serialized.
+    fn to_svg(&self) -> Option<String> {
+        let json = self.to_json()?;
+        let mut svg = canvas_svg(&self.canvas, None);
+        let end = svg.rfind("</svg>")?;
+        svg.insert_str(
+            end,
+            &format!(
+                r#"<metadata id="{METADATA_ID}">{}</metadata>"#,
+                escape(&json)
+            ),
+        );
+        Some(svg)
+    }
+
+    /// Strokes held by an SVG document from [`Clip::to_svg`].
+    fn from_svg(svg: &str) -> Option<Self> {
+        let start = format!(r#"<metadata id="{METADATA_ID}">"#);
+        let (_, rest) = svg.split_once(&start)?;
+        let (json, _) = rest.split_once("</metadata>")?;
+        Self::from_json(&unescape(json))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Last copied strokes, and the system clipboard if there is one.
+#[derive(Resource)]
+pub struct Clipboard {
+    /// JSON of the last copied [`Clip`].
+    json: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    system: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    fn new() -> Self {
+        Self {
+            json: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            system: arboard::Clipboard::new()
+                .inspect_err(|e| warn!("No system clipboard: {e}"))
+                .ok(),
+        }
+    }
+
+    fn put(&mut self, clip: &Clip) {
+        self.json = clip.to_json();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(system), Some(svg)) = (&mut self.system, clip.to_svg())
+            && let Err(e) = system.set_text(svg)
+        {
+            warn!("Cannot copy to system clipboard: {e}");
+        }
+    }
+
+    /// Strokes on the system clipboard if they are ours, or else the last copied here.
+    fn get(&mut self) -> Option<Clip> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(text) = self.system.as_mut().and_then(|x| x.get_text().ok())
+            && let Some(clip) = Clip::from_svg(&text)
+        {
+            return Some(clip);
+        }
+        self.json.as_deref().and_then(Clip::from_json)
+    }
+}
+
+pub struct ClipboardPlugin;
+
+impl Plugin for ClipboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Clipboard::new())
+            .add_event::<ClipboardEvent>()
+            // Pasted strokes are spawned before the selection takes them.
+            .add_systems(
+                Update,
+                (copy_selection, paste_clipboard).before(update_selection),
+            );
+    }
+}
+
+/// The mouse pointer on the canvas.
+#[derive(SystemParam)]
+struct CanvasCursor<'w> {
+    mouse_position: Res<'w, MousePosition>,
+    camera: Single<'w, (&'static Camera, &'static GlobalTransform)>,
+}
+
+impl CanvasCursor<'_> {
+    /// Where the mouse is on the canvas, if over the window.
+    fn position(&self) -> Option<Vec2> {
+        let (camera, camera_transform) = *self.camera;
+        self.mouse_position
+            .0
+            .and_then(|x| camera.viewport_to_world_2d(camera_transform, x).ok())
+    }
+}
+
+fn copy_selection(
+    mut events: EventReader<ClipboardEvent>,
+    mut clipboard: ResMut<Clipboard>,
+    selection: Res<Selection>,
+    curves: Query<(&Curve, &Transform), Without<CurrentCurveMarker>>,
+    mut selection_events: EventWriter<SelectionEvent>,
+    mut overlay_event: EventWriter<OverlayEvent>,
+) {
+    for event in events.read() {
+        if matches!(event, ClipboardEvent::Paste) {
+            continue;
+        }
+        // Strokes are copied as they look, with their transforms baked in.
+        let copied: Vec<Curve> = selection
+            .entities
+            .iter()
+            .filter_map(|x| curves.get(*x).ok())
+            .map(|(curve, transform)| curve.transformed(transform))
+            .collect();
+        if copied.is_empty() {
+            continue;
+        }
+        let count = copied.len();
+        clipboard.put(&Clip {
+            version: FORMAT_VERSION,
+            canvas: Canvas::new(copied, vec![]),
+        });
+        if matches!(event, ClipboardEvent::Cut) {
+            selection_events.write(SelectionEvent::Delete);
+        }
+        overlay_event.write(OverlayEvent::Transient(format!("Copied {count} strokes")));
+    }
+}
+
+fn paste_clipboard(
+    mut events: EventReader<ClipboardEvent>,
+    mut clipboard: ResMut<Clipboard>,
+    mut selection: ResMut<Selection>,
+    mut tool: ResMut<Tool>,
+    cursor: CanvasCursor,
+    mut commands: Commands,
+    mut history: EventWriter<RecordEdit>,
+) {
+    for event in events.read() {
+        if !matches!(event, ClipboardEvent::Paste) {
+            continue;
+        }
+        let Some(clip) = clipboard.get() else {
+            continue;
+        };
+        let offset = cursor
+            .position()
+            .zip(canvas_bounds(&clip.canvas))
+            .map(|(p, bounds)| p - bounds.center())
+            .unwrap_or_default();
+        let offset = Transform::from_translation(offset.extend(0.0));
+        let added: Vec<StrokeRecord> = clip
+            .canvas
+            .curves()
+            .map(|curve| {
+                // Copies are strokes of their own.
+                let mut curve = curve.transformed(&offset);
+                curve.id = Id::new();
+                curve.stamp = Stamp::now();
+                curve.created = Some(now_millis());
+                StrokeRecord {
+                    entity: spawn_curve(&mut commands, curve.clone()),
+                    curve,
+                    transform: Transform::default(),
+                }
+            })
+            .collect();
+        if added.is_empty() {
+            continue;
+        }
+        *tool = Tool::Select;
+        selection.pick(added.iter().map(|x| x.entity).collect());
+        history.write(RecordEdit(Edit::Replace {
+            removed: vec![],
+            added,
+        }));
+    }
+}
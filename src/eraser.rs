@@ -0,0 +1,101 @@
+//! Eraser tool, removing whole strokes or the parts it passes over.
+
+use bevy::prelude::*;
+
+use crate::{
+    CurrentCurveMarker, Curve, Tool,
+    history::{Edit, RecordEdit, StrokeRecord},
+    input::ToolPointer,
+    playback::not_replaying,
+    spawn_curve, stroke,
+};
+
+/// Eraser radius, in logical pixels on screen.
+const ERASER_RADIUS: f32 = 12.0;
+
+/// An eraser drag in progress. All strokes changed in one drag make a single edit.
+#[derive(Resource, Debug, Default)]
+pub struct EraserDrag {
+    last: Option<Vec2>,
+    removed: Vec<StrokeRecord>,
+    added: Vec<StrokeRecord>,
+}
+
+pub struct EraserPlugin;
+
+impl Plugin for EraserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EraserDrag>().add_systems(
+            Update,
+            erase
+                .run_if(|tool: Res<Tool>| tool.is_eraser())
+                .run_if(not_replaying),
+        );
+    }
+}
+
+fn erase(
+    tool: Res<Tool>,
+    pointer: ToolPointer,
+    camera: Single<(&Camera, &GlobalTransform, &Projection)>,
+    curves: Query<(Entity, &Curve, &Transform, &GlobalTransform), Without<CurrentCurveMarker>>,
+    mut drag: ResMut<EraserDrag>,
+    mut commands: Commands,
+    mut history: EventWriter<RecordEdit>,
+) {
+    let (camera, camera_transform, projection) = *camera;
+    let radius = match projection {
+        Projection::Orthographic(ortho) => ERASER_RADIUS * ortho.scale,
+        _ => ERASER_RADIUS,
+    };
+    let Some(current) = pointer
+        .pressed(drag.last.is_some())
+        .and_then(|x| camera.viewport_to_world_2d(camera_transform, x).ok())
+    else {
+        // Released, the drag makes one edit.
+        drag.last = None;
+        let removed = std::mem::take(&mut drag.removed);
+        let added = std::mem::take(&mut drag.added);
+        if !removed.is_empty() || !added.is_empty() {
+            history.write(RecordEdit(Edit::Replace { removed, added }));
+        }
+        return;
+    };
+    let last = drag.last.replace(current).unwrap_or(current);
+
+    for (entity, curve, transform, global) in curves.iter() {
+        // Hit test in the space of the curve.
+        let to_local = global.affine().inverse();
+        let from = to_local.transform_point3(last.extend(0.0)).truncate();
+        let to = to_local.transform_point3(current.extend(0.0)).truncate();
+        let radius = radius / transform.scale.x.abs();
+        let pieces = match *tool {
+            Tool::PartialEraser => stroke::erase(curve, from, to, radius),
+            _ => stroke::hits(curve, from, to, radius).then(Vec::new),
+        };
+        let Some(pieces) = pieces else {
+            continue;
+        };
+
+        commands.entity(entity).despawn();
+        // Pieces of this drag are not known to history yet.
+        if let Some(i) = drag.added.iter().position(|x| x.entity == entity) {
+            drag.added.swap_remove(i);
+        } else {
+            drag.removed.push(StrokeRecord {
+                entity,
+                curve: curve.clone(),
+                transform: *transform,
+            });
+        }
+        for piece in pieces {
+            let entity = spawn_curve(&mut commands, piece.clone());
+            commands.entity(entity).insert(*transform);
+            drag.added.push(StrokeRecord {
+                entity,
+                curve: piece,
+                transform: *transform,
+            });
+        }
+    }
+}
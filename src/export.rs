@@ -0,0 +1,243 @@
+//! Export of canvases to other formats.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{math::cubic_splines::CubicSegment, prelude::*, tasks::IoTaskPool};
+
+use crate::{
+    CurrentCurveMarker, Curve, CyclingMode, form_curve,
+    pages::Notebook,
+    sample_curve,
+    storage::{Canvas, Project, ProjectPath},
+};
+
+pub mod pdf;
+pub mod png;
+pub mod svg;
+
+/// File name to export to when there is no project file.
+const DEFAULT_NAME: &str = "metawrite";
+/// Tessellation detail of outlines, finer than on screen as documents get scaled.
+const OUTLINE_DETAIL: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The first page.
+    Svg,
+    /// All canvases, a page each.
+    Pdf,
+    /// The first page, rasterized.
+    Png,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Png => "png",
+        }
+    }
+
+    /// Format for the extension of `path`.
+    pub fn for_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "svg" => Some(ExportFormat::Svg),
+            "pdf" => Some(ExportFormat::Pdf),
+            "png" => Some(ExportFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// How to export, besides the format.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    pub background: Option<Color>,
+    /// Pixels per inch of raster formats, at [`png::BASE_DPI`] per canvas unit.
+    pub dpi: f32,
+    /// Part of the canvas in raster formats, or all strokes with a margin.
+    pub bounds: Option<Rect>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            background: None,
+            dpi: png::BASE_DPI,
+            bounds: None,
+        }
+    }
+}
+
+/// Export the active page, or all pages for [`ExportFormat::Pdf`], next to the project file or to
+/// the working directory.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ExportEvent(pub ExportFormat);
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportEvent>()
+            .add_systems(Update, do_export);
+    }
+}
+
+/// How a stroke is drawn in vector formats.
+pub enum VectorStroke {
+    /// A line of `width` along Bézier segments, for strokes of even pressure.
+    Path {
+        segments: Vec<[Vec2; 4]>,
+        width: f32,
+    },
+    /// An outline to fill, as there are no lines of varying width.
+    Outline(Vec<Vec2>),
+    /// Straight lines of `width` with mitered corners, for shapes. Each closes into a loop if
+    /// its flag is set.
+    Lines {
+        paths: Vec<(Vec<Vec2>, bool)>,
+        width: f32,
+    },
+}
+
+impl VectorStroke {
+    /// The stroke of `curve`, following the same spline as on screen.
+    pub fn new(curve: &Curve) -> Option<Self> {
+        if curve.points.len() < 2 {
+            return None;
+        }
+        if let Some(shape) = &curve.shape {
+            let width = curve.brush.width_at(curve.pressure_at(0));
+            return Some(VectorStroke::Lines {
+                paths: shape.paths(width, OUTLINE_DETAIL),
+                width,
+            });
+        }
+        let even = (0..curve.points.len()).all(|i| curve.pressure_at(i) == curve.pressure_at(0));
+        if even {
+            let cycling = if curve.closed() {
+                CyclingMode::Cyclic
+            } else {
+                CyclingMode::NotCyclic
+            };
+            let spline = form_curve(&curve.points, curve.spline, cycling)?;
+            return Some(VectorStroke::Path {
+                segments: spline.segments().iter().map(bezier).collect(),
+                width: curve.brush.width_at(curve.pressure_at(0)),
+            });
+        }
+        let (samples, _) = sample_curve(curve, 0, true, OUTLINE_DETAIL);
+        let edges: Vec<[Vec2; 2]> = samples
+            .iter()
+            .map(|(p, direction)| curve.brush.edges(p, *direction))
+            .collect();
+        // Down the left edge and back up the right one.
+        Some(VectorStroke::Outline(
+            edges
+                .iter()
+                .map(|x| x[0])
+                .chain(edges.iter().rev().map(|x| x[1]))
+                .collect(),
+        ))
+    }
+}
+
+/// Bounds of all strokes of `canvas`, including their width.
+pub fn canvas_bounds(canvas: &Canvas) -> Option<Rect> {
+    canvas
+        .curves()
+        .filter_map(|curve| {
+            let half_width = curve.brush.width * 0.5;
+            curve
+                .points
+                .iter()
+                .map(|x| Rect::from_center_half_size(*x, Vec2::splat(half_width)))
+                .reduce(|a, b| a.union(b))
+        })
+        .reduce(|a, b| a.union(b))
+}
+
+/// Control points of the cubic Bézier curve which traces `segment`.
+pub fn bezier(segment: &CubicSegment<Vec2>) -> [Vec2; 4] {
+    let [c0, c1, c2, c3] = segment.coeff;
+    [
+        c0,
+        c0 + c1 / 3.0,
+        c0 + c1 * (2.0 / 3.0) + c2 / 3.0,
+        c0 + c1 + c2 + c3,
+    ]
+}
+
+/// `project` in `format`.
+pub fn export(
+    project: &Project,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<Vec<u8>, String> {
+    let pages = project.pages();
+    let first = pages.first().map(|x| x.1).cloned().unwrap_or_default();
+    match format {
+        ExportFormat::Svg => Ok(svg::canvas_svg(&first, options.background).into_bytes()),
+        ExportFormat::Pdf => Ok(pdf::project_pdf(project, options.background)),
+        ExportFormat::Png => png::canvas_png(&first, options),
+    }
+}
+
+/// Export a project file to `output`, without a window or GPU. The format follows the extension
+/// of `output`.
+pub fn export_file(project: &Path, output: &Path, options: &ExportOptions) -> Result<(), String> {
+    let format = ExportFormat::for_path(output)
+        .ok_or_else(|| format!("{}: unknown export format", output.display()))?;
+    let project =
+        crate::format::load_file(project).map_err(|e| format!("{}: {e}", project.display()))?;
+    let data = export(&project, format, options)?;
+    std::fs::write(output, data).map_err(|e| format!("{}: {e}", output.display()))
+}
+
+/// Where to export in `format`.
+fn export_path(project: &ProjectPath, format: ExportFormat) -> PathBuf {
+    project
+        .0
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_NAME))
+        .with_extension(format.extension())
+}
+
+fn do_export(
+    mut events: EventReader<ExportEvent>,
+    curves: Query<(&Curve, &Transform), Without<CurrentCurveMarker>>,
+    project: Res<ProjectPath>,
+    notebook: Res<Notebook>,
+    clear_color: Res<ClearColor>,
+) {
+    for ExportEvent(format) in events.read() {
+        let path = export_path(&project, *format);
+        let title = path
+            .file_stem()
+            .map_or(DEFAULT_NAME.into(), |x| x.to_string_lossy());
+        let strokes = curves
+            .iter()
+            .map(|(curve, transform)| curve.transformed(transform))
+            .collect();
+        let project = match format {
+            ExportFormat::Pdf => notebook.project(title, strokes),
+            _ => Project::new(title, notebook.active_canvas(strokes)),
+        };
+        let options = ExportOptions {
+            background: Some(clear_color.0),
+            ..default()
+        };
+        let format = *format;
+        IoTaskPool::get()
+            .spawn(async move {
+                let written = export(&project, format, &options)
+                    .and_then(|data| std::fs::write(&path, data).map_err(|e| e.to_string()));
+                match written {
+                    Ok(()) => info!("Exported to {}", path.display()),
+                    Err(e) => warn!("Failed to export to {}: {e}", path.display()),
+                }
+            })
+            .detach();
+    }
+}
@@ -0,0 +1,385 @@
+//! PDF documents of projects, a page per canvas.
+//!
+//! Written by hand as PDF 1.4 with uncompressed streams, which keeps export free of a window, the
+//! GPU and further dependencies. Strokes are drawn as [`VectorStroke`]s, with canvas units taken
+//! as CSS pixels of 0.75pt.
+
+use std::fmt::Write;
+
+use bevy::{
+    color::{Alpha, Color, Srgba},
+    math::Vec2,
+};
+
+use super::{VectorStroke, canvas_bounds};
+use crate::storage::{Canvas, Project, ProjectInfo};
+
+/// Space around the strokes, in canvas units.
+const MARGIN: f32 = 16.0;
+/// Points per canvas unit.
+const PT: f32 = 0.75;
+/// Size of pages without strokes, in points: A4.
+const EMPTY_PAGE: Vec2 = Vec2::new(595.0, 842.0);
+
+/// PDF document of the canvases of `project`, in the order of [`Project::pages`], on a
+/// `background` if given.
+pub fn project_pdf(project: &Project, background: Option<Color>) -> Vec<u8> {
+    let pages = project.pages();
+    let mut pdf = PdfWriter::default();
+    pdf.object(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", page_object(i)))
+        .collect();
+    pdf.object(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    pdf.object(info(&project.title, &project.info).into_bytes());
+    for (i, (_, canvas)) in pages.iter().enumerate() {
+        let page = Page::new(canvas, background);
+        pdf.object(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /ExtGState << {} >> >> /Contents {} 0 R >>",
+                page.size.x,
+                page.size.y,
+                page.states,
+                page_object(i) + 1
+            )
+            .into_bytes(),
+        );
+        pdf.stream(page.content.as_bytes());
+    }
+    pdf.finish()
+}
+
+/// Object number of page `i`, followed by its content stream.
+fn page_object(i: usize) -> usize {
+    4 + 2 * i
+}
+
+/// Document info dictionary.
+fn info(title: &str, info: &ProjectInfo) -> String {
+    let mut out = format!(
+        "<< /Title {} /Producer {}",
+        text(title),
+        text(&format!("metawrite {}", info.version))
+    );
+    if !info.author.is_empty() {
+        let _ = write!(out, " /Author {}", text(&info.author));
+    }
+    if let Some(date) = date(&info.date) {
+        let _ = write!(out, " /CreationDate ({date})");
+    }
+    out.push_str(" >>");
+    out
+}
+
+/// Text string in UTF-16, as hex so nothing needs escaping.
+fn text(s: &str) -> String {
+    let mut out = String::from("<FEFF");
+    for unit in s.encode_utf16() {
+        let _ = write!(out, "{unit:04X}");
+    }
+    out.push('>');
+    out
+}
+
+/// PDF date of a project date, which counts seconds since the Unix epoch.
+fn date(date: &str) -> Option<String> {
+    let secs: i64 = date.trim().parse().ok()?;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    Some(format!(
+        "D:{year:04}{month:02}{day:02}{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    ))
+}
+
+/// Contents of a page.
+struct Page {
+    /// Page size in points.
+    size: Vec2,
+    /// Graphics states for each opacity used, as `/GSn` entries.
+    states: String,
+    content: String,
+    /// Opacities of the graphics states so far.
+    alphas: Vec<f32>,
+}
+
+impl Page {
+    fn new(canvas: &Canvas, background: Option<Color>) -> Self {
+        let mut page = Page {
+            size: EMPTY_PAGE,
+            states: String::new(),
+            content: String::new(),
+            alphas: vec![],
+        };
+        let bounds = canvas_bounds(canvas).map(|x| x.inflate(MARGIN));
+        if let Some(bounds) = bounds {
+            page.size = bounds.size() * PT;
+            // PDF y points up like canvas y, so this only scales and moves to the page origin.
+            let origin = -bounds.min * PT;
+            let _ = writeln!(
+                page.content,
+                "{PT} 0 0 {PT} {:.2} {:.2} cm",
+                origin.x, origin.y
+            );
+        }
+        if let Some(background) = background {
+            // The whole page, in canvas units.
+            let (min, size) = bounds.map_or((Vec2::ZERO, page.size), |x| (x.min, x.size()));
+            page.fill(background);
+            let _ = writeln!(
+                page.content,
+                "{:.2} {:.2} {:.2} {:.2} re f",
+                min.x, min.y, size.x, size.y
+            );
+        }
+        for curve in canvas.curves() {
+            let Some(stroke) = VectorStroke::new(&curve) else {
+                continue;
+            };
+            let color = curve.brush.draw_color();
+            match stroke {
+                VectorStroke::Path { segments, width } => {
+                    page.stroke(color);
+                    let _ = writeln!(page.content, "{width:.2} w 1 j 1 J");
+                    for (i, [b0, b1, b2, b3]) in segments.into_iter().enumerate() {
+                        if i == 0 {
+                            page.point(b0, "m");
+                        }
+                        let _ = writeln!(
+                            page.content,
+                            "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+                            b1.x, b1.y, b2.x, b2.y, b3.x, b3.y
+                        );
+                    }
+                    page.content.push_str("S\n");
+                }
+                VectorStroke::Outline(outline) => {
+                    page.fill(color);
+                    for (i, p) in outline.into_iter().enumerate() {
+                        page.point(p, if i == 0 { "m" } else { "l" });
+                    }
+                    page.content.push_str("h f\n");
+                }
+                VectorStroke::Lines { paths, width } => {
+                    page.stroke(color);
+                    let _ = writeln!(page.content, "{width:.2} w 0 j 0 J");
+                    for (points, closed) in paths {
+                        for (i, p) in points.into_iter().enumerate() {
+                            page.point(p, if i == 0 { "m" } else { "l" });
+                        }
+                        if closed {
+                            page.content.push_str("h\n");
+                        }
+                    }
+                    page.content.push_str("S\n");
+                }
+            }
+        }
+        page
+    }
+
+    fn point(&mut self, p: Vec2, operator: &str) {
+        let _ = writeln!(self.content, "{:.2} {:.2} {operator}", p.x, p.y);
+    }
+
+    /// Set the fill color, and its opacity.
+    fn fill(&mut self, color: Color) {
+        let color = self.paint(color);
+        let _ = writeln!(self.content, "{color} rg");
+    }
+
+    /// Set the stroke color, and its opacity.
+    fn stroke(&mut self, color: Color) {
+        let color = self.paint(color);
+        let _ = writeln!(self.content, "{color} RG");
+    }
+
+    /// Select a graphics state for the opacity of `color`, and return its components.
+    fn paint(&mut self, color: Color) -> String {
+        let color = Srgba::from(color);
+        let alpha = color.alpha();
+        let state = match self.alphas.iter().position(|x| *x == alpha) {
+            Some(i) => i,
+            None => {
+                let i = self.alphas.len();
+                self.alphas.push(alpha);
+                let _ = write!(self.states, "/GS{i} << /CA {alpha:.3} /ca {alpha:.3} >> ");
+                i
+            }
+        };
+        let _ = writeln!(self.content, "/GS{state} gs");
+        format!("{:.3} {:.3} {:.3}", color.red, color.green, color.blue)
+    }
+}
+
+/// Numbers objects in the order written, and keeps their offsets for the cross-reference table.
+#[derive(Default)]
+struct PdfWriter {
+    out: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn begin(&mut self) {
+        if self.out.is_empty() {
+            // The binary comment marks the file as binary to transfer programs.
+            self.out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+        }
+        self.offsets.push(self.out.len());
+        let _ = writeln!(Bytes(&mut self.out), "{} 0 obj", self.offsets.len());
+    }
+
+    fn object(&mut self, body: Vec<u8>) {
+        self.begin();
+        self.out.extend(body);
+        self.out.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn stream(&mut self, data: &[u8]) {
+        self.begin();
+        let _ = writeln!(Bytes(&mut self.out), "<< /Length {} >>", data.len());
+        self.out.extend_from_slice(b"stream\n");
+        self.out.extend_from_slice(data);
+        self.out.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let xref = self.out.len();
+        let mut out = Bytes(&mut self.out);
+        let _ = write!(
+            out,
+            "xref\n0 {}\n0000000000 65535 f \n",
+            self.offsets.len() + 1
+        );
+        for offset in &self.offsets {
+            let _ = writeln!(out, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.offsets.len() + 1
+        );
+        self.out
+    }
+}
+
+/// Formatting into a byte buffer.
+struct Bytes<'a>(&'a mut Vec<u8>);
+
+impl std::fmt::Write for Bytes<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(pages: usize) -> Project {
+        let stroke =
+            serde_json::from_str(r#"{"points": [[0, 0], [10, 10], [20, 0]], "which": 2}"#).unwrap();
+        let mut project = Project::new("Notes", Canvas::new(vec![stroke], vec![]));
+        for i in 1..pages {
+            let name = format!("page {i}");
+            project.canvas.insert(name.clone(), Canvas::default());
+            project.pages.push(name);
+        }
+        project
+    }
+
+    /// Position of `needle` in `pdf` at or after `from`.
+    fn find(pdf: &[u8], needle: &str, from: usize) -> Option<usize> {
+        pdf[from..]
+            .windows(needle.len())
+            .position(|x| x == needle.as_bytes())
+            .map(|x| x + from)
+    }
+
+    #[test]
+    fn xref_points_at_objects() {
+        let pdf = project_pdf(&project(3), None);
+        let text = String::from_utf8_lossy(&pdf);
+        let startxref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|x| x.lines().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[startxref..].starts_with(b"xref\n"));
+        let table = &text[text.rfind("xref\n0 ").unwrap()..];
+        let count: usize = table.lines().nth(1).unwrap()[2..].parse().unwrap();
+        // Catalog, pages, info, then a page and its contents for each canvas.
+        assert_eq!(count, 1 + 3 + 2 * 3);
+        for (i, line) in table.lines().skip(3).take(count - 1).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            let header = format!("{} 0 obj\n", i + 1);
+            assert_eq!(find(&pdf, &header, 0), Some(offset), "object {}", i + 1);
+        }
+        assert!(text.contains("/Count 3"));
+    }
+
+    #[test]
+    fn stream_lengths_match() {
+        let pdf = project_pdf(&project(1), Some(Color::WHITE));
+        let text = String::from_utf8_lossy(&pdf);
+        let (_, rest) = text.split_once("<< /Length ").unwrap();
+        let (length, rest) = rest.split_once(" >>\nstream\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        assert!(rest[length..].starts_with("\nendstream"));
+        assert!(rest[..length].contains(" re f"));
+    }
+
+    /// Content of a page with one stroke through three points, in `spline` mode.
+    fn content(spline: &str) -> String {
+        let stroke = serde_json::from_str(&format!(
+            r#"{{"points": [[0, 0], [10, 10], [20, 0]], "which": 2, "spline": "{spline}"}}"#
+        ))
+        .unwrap();
+        Page::new(&Canvas::new(vec![stroke], vec![]), None).content
+    }
+
+    #[test]
+    fn strokes_are_drawn() {
+        // The cardinal spline mirrors its end points, so three points make two segments.
+        let cardinal = content("Cardinal");
+        assert_eq!(cardinal.matches(" m\n").count(), 1);
+        assert_eq!(cardinal.matches(" c\n").count(), 2);
+        assert!(cardinal.ends_with("S\n"));
+        assert!(content("Hermite").matches(" c\n").count() > 0);
+    }
+
+    #[test]
+    fn dates_are_civil() {
+        assert_eq!(date("0").as_deref(), Some("D:19700101000000Z"));
+        assert_eq!(date("951827696").as_deref(), Some("D:20000229123456Z"));
+        assert_eq!(date("-1").as_deref(), Some("D:19691231235959Z"));
+        assert_eq!(date("yesterday"), None);
+    }
+
+    #[test]
+    fn text_is_utf16_hex() {
+        assert_eq!(text("Aé"), "<FEFF004100E9>");
+        assert!(project_pdf(&project(1), None).starts_with(b"%PDF-1.4\n"));
+    }
+}
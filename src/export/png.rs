@@ -0,0 +1,200 @@
+//! PNG images of canvases, rasterized on the CPU.
+//!
+//! Strokes are drawn as [`VectorStroke`]s with tiny-skia rather than through the render graph, so
+//! images can be made without a window or GPU.
+
+use bevy::{
+    color::{Color, ColorToComponents, Srgba},
+    math::{Rect, Vec2},
+};
+use tiny_skia::{FillRule, LineCap, LineJoin, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+use super::{ExportOptions, VectorStroke, canvas_bounds};
+use crate::storage::Canvas;
+
+/// Space around the strokes, in canvas units.
+const MARGIN: f32 = 16.0;
+/// Resolution at which a canvas unit takes a pixel, like CSS pixels.
+pub const BASE_DPI: f32 = 96.0;
+/// Widest and tallest image, in pixels.
+const MAX_SIDE: u32 = 16384;
+
+/// PNG image of `canvas`.
+pub fn canvas_png(canvas: &Canvas, options: &ExportOptions) -> Result<Vec<u8>, String> {
+    canvas_pixmap(canvas, options)?
+        .encode_png()
+        .map_err(|e| e.to_string())
+}
+
+/// PNG image of all of `canvas` fitting in `size` pixels square, for previews of projects.
+pub fn thumbnail(canvas: &Canvas, size: u32, background: Option<Color>) -> Result<Vec<u8>, String> {
+    let bounds = default_bounds(canvas);
+    let options = ExportOptions {
+        background,
+        dpi: BASE_DPI * size as f32 / bounds.width().max(bounds.height()),
+        bounds: Some(bounds),
+    };
+    canvas_png(canvas, &options)
+}
+
+fn default_bounds(canvas: &Canvas) -> Rect {
+    canvas_bounds(canvas)
+        .unwrap_or(Rect::from_center_size(Vec2::ZERO, Vec2::ONE))
+        .inflate(MARGIN)
+}
+
+/// Image of `canvas`, with premultiplied alpha.
+pub fn canvas_pixmap(canvas: &Canvas, options: &ExportOptions) -> Result<Pixmap, String> {
+    let bounds = options.bounds.unwrap_or_else(|| default_bounds(canvas));
+    let scale = options.dpi / BASE_DPI;
+    let size = (bounds.size() * scale).ceil().max(Vec2::ONE);
+    if !(size.x <= MAX_SIDE as f32 && size.y <= MAX_SIDE as f32) {
+        return Err(format!(
+            "{}x{} pixels is larger than {MAX_SIDE} pixels a side",
+            size.x, size.y
+        ));
+    }
+    let mut pixmap =
+        Pixmap::new(size.x as u32, size.y as u32).ok_or_else(|| "image is empty".to_owned())?;
+    if let Some(background) = options.background {
+        pixmap.fill(color(background));
+    }
+    // Canvas y points up, image y down.
+    let transform = Transform::from_row(
+        scale,
+        0.0,
+        0.0,
+        -scale,
+        -bounds.min.x * scale,
+        bounds.max.y * scale,
+    );
+    for curve in canvas.curves() {
+        let Some(stroke) = VectorStroke::new(&curve) else {
+            continue;
+        };
+        let mut paint = Paint::default();
+        paint.set_color(color(curve.brush.draw_color()));
+        let mut path = PathBuilder::new();
+        match stroke {
+            VectorStroke::Path { segments, width } => {
+                for (i, [b0, b1, b2, b3]) in segments.into_iter().enumerate() {
+                    if i == 0 {
+                        path.move_to(b0.x, b0.y);
+                    }
+                    path.cubic_to(b1.x, b1.y, b2.x, b2.y, b3.x, b3.y);
+                }
+                let Some(path) = path.finish() else {
+                    continue;
+                };
+                let stroke = Stroke {
+                    width,
+                    line_cap: LineCap::Round,
+                    line_join: LineJoin::Round,
+                    ..Default::default()
+                };
+                pixmap.stroke_path(&path, &paint, &stroke, transform, None);
+            }
+            VectorStroke::Outline(outline) => {
+                for (i, p) in outline.into_iter().enumerate() {
+                    if i == 0 {
+                        path.move_to(p.x, p.y);
+                    } else {
+                        path.line_to(p.x, p.y);
+                    }
+                }
+                path.close();
+                let Some(path) = path.finish() else {
+                    continue;
+                };
+                pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+            }
+            VectorStroke::Lines { paths, width } => {
+                for (points, closed) in paths {
+                    for (i, p) in points.into_iter().enumerate() {
+                        if i == 0 {
+                            path.move_to(p.x, p.y);
+                        } else {
+                            path.line_to(p.x, p.y);
+                        }
+                    }
+                    if closed {
+                        path.close();
+                    }
+                }
+                let Some(path) = path.finish() else {
+                    continue;
+                };
+                let stroke = Stroke {
+                    width,
+                    line_join: LineJoin::Miter,
+                    ..Default::default()
+                };
+                pixmap.stroke_path(&path, &paint, &stroke, transform, None);
+            }
+        }
+    }
+    Ok(pixmap)
+}
+
+fn color(color: Color) -> tiny_skia::Color {
+    let [r, g, b, a] = Srgba::from(color).to_f32_array().map(|x| x.clamp(0.0, 1.0));
+    tiny_skia::Color::from_rgba(r, g, b, a).unwrap_or(tiny_skia::Color::BLACK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canvas() -> Canvas {
+        let mut stroke: crate::Curve =
+            serde_json::from_str(r#"{"points": [[0, 0], [10, 10], [20, 0]], "which": 2}"#).unwrap();
+        stroke.brush.color = bevy::math::Vec4::W;
+        Canvas::new(vec![stroke], vec![])
+    }
+
+    #[test]
+    fn size_follows_dpi() {
+        let bounds = Rect::new(-5.0, -5.0, 25.0, 15.0);
+        for (dpi, size) in [(BASE_DPI, (30, 20)), (2.0 * BASE_DPI, (60, 40))] {
+            let options = ExportOptions {
+                dpi,
+                bounds: Some(bounds),
+                ..Default::default()
+            };
+            let pixmap = canvas_pixmap(&canvas(), &options).unwrap();
+            assert_eq!((pixmap.width(), pixmap.height()), size);
+        }
+        let huge = ExportOptions {
+            dpi: 100.0 * BASE_DPI,
+            bounds: Some(Rect::new(0.0, 0.0, 1000.0, 1.0)),
+            ..Default::default()
+        };
+        assert!(canvas_pixmap(&canvas(), &huge).is_err());
+    }
+
+    #[test]
+    fn strokes_land_right_side_up() {
+        let options = ExportOptions {
+            background: Some(Color::WHITE),
+            dpi: 2.0 * BASE_DPI,
+            bounds: Some(Rect::new(-5.0, -5.0, 25.0, 15.0)),
+        };
+        let pixmap = canvas_pixmap(&canvas(), &options).unwrap();
+        let white = |x, y| {
+            let pixel = pixmap.pixel(x, y).unwrap();
+            (pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()) == (255, 255, 255, 255)
+        };
+        // The top of the stroke is at (10, 10), near the top of the image.
+        assert!(!white(30, 10));
+        assert!(white(30, 36));
+        assert!(white(0, 0));
+    }
+
+    #[test]
+    fn thumbnails_fit() {
+        let png = thumbnail(&canvas(), 64, None).unwrap();
+        let pixmap = Pixmap::decode_png(&png).unwrap();
+        assert_eq!(pixmap.width().max(pixmap.height()), 64);
+        assert!(thumbnail(&Canvas::default(), 64, None).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//! SVG documents of canvases.
+//!
+//! Strokes are drawn as [`VectorStroke`]s, with canvas units taken as pixels.
+
+use std::fmt::Write;
+
+use bevy::{
+    color::{Alpha, Color, Srgba},
+    math::{Rect, Vec2},
+};
+
+use super::{VectorStroke, canvas_bounds};
+use crate::{Curve, storage::Canvas};
+
+/// Space around the strokes, in canvas units.
+const MARGIN: f32 = 16.0;
+
+/// SVG document of `canvas`, on a `background` if given.
+pub fn canvas_svg(canvas: &Canvas, background: Option<Color>) -> String {
+    let bounds = canvas_bounds(canvas)
+        .unwrap_or(Rect::from_center_size(Vec2::ZERO, Vec2::ONE))
+        .inflate(MARGIN);
+    // Canvas y points up, SVG y down.
+    let (x, y) = (bounds.min.x, -bounds.max.y);
+    let (width, height) = (bounds.width(), bounds.height());
+
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width:.0}" height="{height:.0}" viewBox="{x:.2} {y:.2} {width:.2} {height:.2}">"#
+    );
+    if let Some(background) = background {
+        let (fill, opacity) = paint(background);
+        let _ = writeln!(
+            out,
+            r#"<rect x="{x:.2}" y="{y:.2}" width="{width:.2}" height="{height:.2}" fill="{fill}" fill-opacity="{opacity:.3}"/>"#
+        );
+    }
+    for curve in canvas.curves() {
+        stroke(&mut out, &curve);
+    }
+    let _ = writeln!(out, "</svg>");
+    out
+}
+
+/// Color and opacity of `color`, which SVG takes apart.
+fn paint(color: Color) -> (String, f32) {
+    let color = Srgba::from(color);
+    (color.with_alpha(1.0).to_hex(), color.alpha)
+}
+
+fn point(out: &mut String, p: Vec2) {
+    let _ = write!(out, "{:.2},{:.2}", p.x, -p.y);
+}
+
+fn stroke(out: &mut String, curve: &Curve) {
+    let Some(stroke) = VectorStroke::new(curve) else {
+        return;
+    };
+    let (color, opacity) = paint(curve.brush.draw_color());
+    let mut d = String::new();
+    match stroke {
+        VectorStroke::Path { segments, width } => {
+            for (i, [b0, b1, b2, b3]) in segments.into_iter().enumerate() {
+                if i == 0 {
+                    d.push('M');
+                    point(&mut d, b0);
+                }
+                for (command, p) in [(" C", b1), (" ", b2), (" ", b3)] {
+                    d.push_str(command);
+                    point(&mut d, p);
+                }
+            }
+            let _ = writeln!(
+                out,
+                r#"<path d="{d}" fill="none" stroke="{color}" stroke-opacity="{opacity:.3}" stroke-width="{width:.2}" stroke-linejoin="round"/>"#
+            );
+        }
+        VectorStroke::Outline(outline) => {
+            for (i, p) in outline.into_iter().enumerate() {
+                d.push_str(if i == 0 { "M" } else { " L" });
+                point(&mut d, p);
+            }
+            let _ = writeln!(
+                out,
+                r#"<path d="{d} Z" fill="{color}" fill-opacity="{opacity:.3}"/>"#
+            );
+        }
+        VectorStroke::Lines { paths, width } => {
+            for (points, closed) in paths {
+                for (i, p) in points.into_iter().enumerate() {
+                    d.push_str(if i == 0 { " M" } else { " L" });
+                    point(&mut d, p);
+                }
+                if closed {
+                    d.push_str(" Z");
+                }
+            }
+            let _ = writeln!(
+                out,
+                r#"<path d="{}" fill="none" stroke="{color}" stroke-opacity="{opacity:.3}" stroke-width="{width:.2}"/>"#,
+                d.trim_start()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &str) -> Curve {
+        serde_json::from_str(&format!(r#"{{"points": {points}, "which": 0}}"#)).unwrap()
+    }
+
+    #[test]
+    fn path_per_stroke() {
+        let canvas = Canvas::new(
+            vec![
+                curve("[[0, 0], [10, 10], [20, 0]]"),
+                curve("[[0, 20], [20, 20]]"),
+                curve("[[5, 5]]"),
+            ],
+            vec![],
+        );
+        let svg = canvas_svg(&canvas, None);
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<path ").count(), 2);
+        assert!(!svg.contains("<rect"));
+    }
+
+    #[test]
+    fn y_is_flipped() {
+        let canvas = Canvas::new(vec![curve("[[0, 0], [10, 10]]")], vec![]);
+        let svg = canvas_svg(&canvas, Some(Color::WHITE));
+        assert!(svg.contains(r#"d="M0.00,"#));
+        assert!(svg.contains("10.00,-10.00"));
+        assert!(svg.contains(r##"fill="#FFFFFF" fill-opacity="1.000""##));
+    }
+}
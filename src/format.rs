@@ -0,0 +1,408 @@
+//! The `.metawrite` file format.
+//!
+//! A file starts with a fixed 12 byte header, followed by the serialized [`Project`]:
+//!
+//! | offset | size | content                                    |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 8    | magic, `b"METAWRT\0"`                      |
+//! | 8      | 2    | format version, little endian `u16`        |
+//! | 10     | 1    | payload encoding, see [`Encoding`]         |
+//! | 11     | 1    | reserved, must be 0                        |
+//! | 12     | ..   | payload                                    |
+//!
+//! The payload is MessagePack with named fields, or JSON for debugging. Both are self-describing,
+//! so a payload of an older version is decoded into a generic value first, then brought up to
+//! [`FORMAT_VERSION`] by [`migrate`] before turning it into a [`Project`].
+//!
+//! Data without the header is taken as version 0, which is the bare JSON written by early builds.
+//!
+//! When changing anything serialized under [`Project`], bump [`FORMAT_VERSION`] and append a step
+//! to [`MIGRATIONS`]. Fields with a `#[serde(default)]` still need the bump, so older builds can
+//! refuse newer files instead of silently dropping data.
+
+use std::{fmt, path::Path};
+
+use serde_json::Value;
+
+use crate::{merge::Id, storage::Project};
+
+pub const MAGIC: &[u8; 8] = b"METAWRT\0";
+pub const HEADER_SIZE: usize = 12;
+/// Current format version.
+pub const FORMAT_VERSION: u16 = 9;
+/// Default file extension.
+pub const EXTENSION: &str = "metawrite";
+
+/// A step that takes a payload one version up.
+type Migration = fn(Value) -> Result<Value, FormatError>;
+
+/// Migration steps, `MIGRATIONS[n]` takes a payload of version `n` to version `n + 1`.
+const MIGRATIONS: &[Migration] = &[
+    add_container,
+    add_pressure,
+    add_brush,
+    add_page_order,
+    add_spline_mode,
+    add_shapes,
+    add_ids,
+    add_peek_ids,
+    add_times,
+];
+const _: () = assert!(MIGRATIONS.len() == FORMAT_VERSION as usize);
+
+/// Encoding of the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// MessagePack, compact.
+    #[default]
+    Binary = 0,
+    /// Pretty printed JSON, for debugging.
+    Json = 1,
+}
+
+impl Encoding {
+    /// `.json` files get JSON, everything else is binary.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("json") => Encoding::Json,
+            _ => Encoding::Binary,
+        }
+    }
+}
+
+impl TryFrom<u8> for Encoding {
+    type Error = FormatError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Encoding::Binary),
+            1 => Ok(Encoding::Json),
+            x => Err(FormatError::UnknownEncoding(x)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(std::io::Error),
+    /// The file is written by a newer build.
+    TooNew(u16),
+    UnknownEncoding(u8),
+    Truncated,
+    Encode(String),
+    Decode(String),
+    Migrate {
+        from: u16,
+        reason: String,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "io error: {e}"),
+            FormatError::TooNew(v) => write!(
+                f,
+                "file format version {v} is newer than supported version {FORMAT_VERSION}"
+            ),
+            FormatError::UnknownEncoding(x) => write!(f, "unknown payload encoding {x}"),
+            FormatError::Truncated => f.write_str("file is truncated"),
+            FormatError::Encode(e) => write!(f, "failed to encode: {e}"),
+            FormatError::Decode(e) => write!(f, "failed to decode: {e}"),
+            FormatError::Migrate { from, reason } => {
+                write!(f, "failed to migrate from version {from}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<std::io::Error> for FormatError {
+    fn from(value: std::io::Error) -> Self {
+        FormatError::Io(value)
+    }
+}
+
+/// Serialize a project into a file of the current version.
+pub fn encode(project: &Project, encoding: Encoding) -> Result<Vec<u8>, FormatError> {
+    let mut data = Vec::with_capacity(HEADER_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    data.push(encoding as u8);
+    data.push(0);
+    match encoding {
+        Encoding::Binary => data.extend(
+            rmp_serde::to_vec_named(project).map_err(|e| FormatError::Encode(e.to_string()))?,
+        ),
+        Encoding::Json => serde_json::to_writer_pretty(&mut data, project)
+            .map_err(|e| FormatError::Encode(e.to_string()))?,
+    }
+    Ok(data)
+}
+
+/// Deserialize a project, migrating it from older versions when needed.
+pub fn decode(data: &[u8]) -> Result<Project, FormatError> {
+    let (version, value) = decode_value(data)?;
+    let value = migrate(version, value)?;
+    serde_json::from_value(value).map_err(|e| FormatError::Decode(e.to_string()))
+}
+
+/// Split a file into its version and its untyped payload.
+fn decode_value(data: &[u8]) -> Result<(u16, Value), FormatError> {
+    if !data.starts_with(MAGIC) {
+        // Version 0 has no header.
+        let value = serde_json::from_slice(data).map_err(|e| FormatError::Decode(e.to_string()))?;
+        return Ok((0, value));
+    }
+    if data.len() < HEADER_SIZE {
+        return Err(FormatError::Truncated);
+    }
+    let version = u16::from_le_bytes([data[8], data[9]]);
+    if version > FORMAT_VERSION {
+        return Err(FormatError::TooNew(version));
+    }
+    let payload = &data[HEADER_SIZE..];
+    let value = match Encoding::try_from(data[10])? {
+        Encoding::Binary => {
+            rmp_serde::from_slice(payload).map_err(|e| FormatError::Decode(e.to_string()))?
+        }
+        Encoding::Json => {
+            serde_json::from_slice(payload).map_err(|e| FormatError::Decode(e.to_string()))?
+        }
+    };
+    Ok((version, value))
+}
+
+/// 0 -> 1: Only the container is added.
+fn add_container(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// 1 -> 2: `Curve.pressure` is added, missing pressure is full pressure.
+fn add_pressure(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// 2 -> 3: `Curve.brush` is added, missing brush is the default pen.
+fn add_brush(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// 3 -> 4: `Project.pages` is added, missing order puts the main canvas first, then the others by
+/// name.
+fn add_page_order(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// 4 -> 5: `Curve.spline` and `Curve.cycling` are added, missing modes are an open Catmull-Rom
+/// spline.
+fn add_spline_mode(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// 5 -> 6: `Elements::Shape` holds a [`Shape`](crate::shape::Shape), and `Curve.shape` is added.
+/// Shape elements of older files were always empty, and are dropped.
+fn add_shapes(mut value: Value) -> Result<Value, FormatError> {
+    let Some(canvas) = value.get_mut("canvas").and_then(Value::as_object_mut) else {
+        return Ok(value);
+    };
+    for canvas in canvas.values_mut() {
+        if let Some(elements) = canvas.get_mut("elements").and_then(Value::as_array_mut) {
+            elements.retain(|x| x.get("Shape").is_none());
+        }
+    }
+    Ok(value)
+}
+
+/// 6 -> 7: Strokes and shapes get an `id` and a `stamp`, and `Canvas.removed` is added. Ids of
+/// older strokes are made from their place and content, so copies of one file agree on them and
+/// sort them in the order they were drawn. Missing stamps are older than any change.
+fn add_ids(mut value: Value) -> Result<Value, FormatError> {
+    let Some(canvas) = value.get_mut("canvas").and_then(Value::as_object_mut) else {
+        return Ok(value);
+    };
+    for canvas in canvas.values_mut() {
+        let mut i = 0;
+        let mut name = |item: &mut Value| {
+            let Some(item) = item.as_object_mut() else {
+                return;
+            };
+            let id = made_up_id(i, &Value::Object(item.clone()));
+            item.insert("id".to_owned(), Value::String(id.to_string()));
+            i += 1;
+        };
+        if let Some(strokes) = canvas.get_mut("strokes").and_then(Value::as_array_mut) {
+            strokes.iter_mut().for_each(&mut name);
+        }
+        if let Some(elements) = canvas.get_mut("elements").and_then(Value::as_array_mut) {
+            for element in elements.iter_mut() {
+                if let Some(item) = element.get_mut("Curve") {
+                    name(item);
+                } else if let Some(item) = element.get_mut("Shape") {
+                    name(item);
+                }
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// 7 -> 8: `Elements::Peek` holds its text along with an `id`, made up as in [`add_ids`] after
+/// those of the strokes. Strokes, shapes and peeks get `created`, which older files do not know.
+fn add_peek_ids(mut value: Value) -> Result<Value, FormatError> {
+    let Some(canvas) = value.get_mut("canvas").and_then(Value::as_object_mut) else {
+        return Ok(value);
+    };
+    for canvas in canvas.values_mut() {
+        let strokes = canvas
+            .get("strokes")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len);
+        let Some(elements) = canvas.get_mut("elements").and_then(Value::as_array_mut) else {
+            continue;
+        };
+        let mut i = (strokes + elements.len()) as u64;
+        for element in elements.iter_mut() {
+            let Some(peek) = element.get_mut("Peek") else {
+                continue;
+            };
+            let id = made_up_id(i, peek);
+            *peek = serde_json::json!({ "text": peek.take(), "id": id.to_string() });
+            i += 1;
+        }
+    }
+    Ok(value)
+}
+
+/// 8 -> 9: `Curve.times` and `Shape.duration` are added. Older strokes have no times, and are
+/// replayed at an even pace.
+fn add_times(value: Value) -> Result<Value, FormatError> {
+    Ok(value)
+}
+
+/// Id of the `i`th item of a canvas, made from its place and content.
+fn made_up_id(i: u64, item: &Value) -> Id {
+    // FNV-1a of the content.
+    let hash = item
+        .to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |h, x| {
+            (h ^ u64::from(x)).wrapping_mul(0x100000001b3)
+        });
+    Id::from_parts(i, u128::from(hash))
+}
+
+/// Bring a payload of `version` up to [`FORMAT_VERSION`].
+pub fn migrate(version: u16, mut value: Value) -> Result<Value, FormatError> {
+    for (from, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        value = step(value).map_err(|e| FormatError::Migrate {
+            from: from as u16,
+            reason: e.to_string(),
+        })?;
+    }
+    Ok(value)
+}
+
+/// Write a project to `path`, choosing encoding by its extension.
+pub fn save_file(path: &Path, project: &Project) -> Result<(), FormatError> {
+    let data = encode(project, Encoding::for_path(path))?;
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// Read a project from `path`.
+pub fn load_file(path: &Path) -> Result<Project, FormatError> {
+    decode(&std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MAIN_CANVAS;
+
+    /// A file of `version` holding `json` as its payload.
+    fn file(version: u16, json: &str) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(version.to_le_bytes());
+        data.extend([Encoding::Json as u8, 0]);
+        data.extend(json.as_bytes());
+        data
+    }
+
+    fn ids(project: &Project) -> Vec<Id> {
+        project.canvas[MAIN_CANVAS]
+            .strokes
+            .iter()
+            .map(|x| x.id())
+            .chain(project.canvas[MAIN_CANVAS].elements.iter().map(|x| x.id()))
+            .collect()
+    }
+
+    const V0: &str = r#"{
+        "title": "Notes",
+        "info": {"author": "", "version": "0.1", "date": "0"},
+        "canvas": {".main": {
+            "strokes": [
+                {"points": [[0, 0], [1, 1]], "which": 1},
+                {"points": [[2, 0], [3, 1], [4, 0]], "which": 2}
+            ],
+            "elements": [{"Peek": "note"}]
+        }}
+    }"#;
+
+    #[test]
+    fn bare_json_round_trips() {
+        let project = decode(V0.as_bytes()).unwrap();
+        let canvas = &project.canvas[MAIN_CANVAS];
+        assert_eq!(canvas.strokes.len(), 2);
+        assert_eq!(canvas.strokes[1].points.len(), 3);
+        assert_eq!(canvas.elements.len(), 1);
+        let back = decode(&encode(&project, Encoding::Binary).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&project).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_newer_and_truncated_files() {
+        assert!(matches!(
+            decode(&file(FORMAT_VERSION + 1, "{}")),
+            Err(FormatError::TooNew(v)) if v == FORMAT_VERSION + 1
+        ));
+        assert!(matches!(decode(&MAGIC[..]), Err(FormatError::Truncated)));
+        assert!(matches!(
+            decode(&file(FORMAT_VERSION, "")[..HEADER_SIZE - 1]),
+            Err(FormatError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn migrated_ids_agree_across_copies() {
+        let a = decode(&file(6, V0)).unwrap();
+        let b = decode(&file(6, V0)).unwrap();
+        assert_eq!(ids(&a).len(), 3);
+        assert_eq!(ids(&a), ids(&b));
+        // Ids differ between strokes, and sort in the order they were drawn.
+        let mut sorted = ids(&a);
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, ids(&a));
+        // Peeks get the same ids when migrated from files which have stroke ids already.
+        let c = decode(&file(7, V0)).unwrap();
+        assert_eq!(ids(&c)[2], ids(&a)[2]);
+    }
+
+    #[test]
+    fn encodings_decode_alike() {
+        let project = decode(&file(6, V0)).unwrap();
+        let binary = decode(&encode(&project, Encoding::Binary).unwrap()).unwrap();
+        let json = decode(&encode(&project, Encoding::Json).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&binary).unwrap(),
+            serde_json::to_value(&json).unwrap()
+        );
+        assert_eq!(ids(&binary), ids(&project));
+    }
+}
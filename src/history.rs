@@ -0,0 +1,275 @@
+//! Undo and redo of stroke operations.
+//!
+//! Tools send a [`RecordEdit`] after changing strokes, and [`HistoryEvent`] steps through the
+//! recorded edits. Strokes brought back by undo or redo are spawned anew, so recorded entities are
+//! remapped to the new ones.
+//!
+//! Each page has a history of its own. [`History`] is that of the page shown, and those of the
+//! others are kept as [`StoredHistory`] with the pages.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{Curve, merge::Id, pages::PageEvent, spawn_curve, storage::Canvas};
+
+/// Edits kept for undo.
+const MAX_HISTORY: usize = 256;
+
+/// A stroke as it was when an edit is recorded.
+#[derive(Debug, Clone)]
+pub struct StrokeRecord {
+    pub entity: Entity,
+    pub curve: Curve,
+    pub transform: Transform,
+}
+
+#[derive(Debug, Clone)]
+pub enum Edit {
+    /// Strokes in `removed` are replaced with those in `added`. Either may be empty.
+    Replace {
+        removed: Vec<StrokeRecord>,
+        added: Vec<StrokeRecord>,
+    },
+    /// Strokes are moved from the first transform to the second.
+    Transform(Vec<(Entity, Transform, Transform)>),
+    /// The page `name` was deleted from place `index` of the page order, with its `canvas` and
+    /// `history`.
+    DeletePage {
+        name: String,
+        index: usize,
+        canvas: Canvas,
+        history: StoredHistory,
+    },
+}
+
+impl Edit {
+    pub fn add(stroke: StrokeRecord) -> Self {
+        Edit::Replace {
+            removed: vec![],
+            added: vec![stroke],
+        }
+    }
+
+    pub fn remove(strokes: Vec<StrokeRecord>) -> Self {
+        Edit::Replace {
+            removed: strokes,
+            added: vec![],
+        }
+    }
+
+    fn remap(&mut self, from: Entity, to: Entity) {
+        match self {
+            Edit::Replace { removed, added } => removed
+                .iter_mut()
+                .chain(added.iter_mut())
+                .filter(|x| x.entity == from)
+                .for_each(|x| x.entity = to),
+            Edit::Transform(strokes) => strokes
+                .iter_mut()
+                .filter(|x| x.0 == from)
+                .for_each(|x| x.0 = to),
+            Edit::DeletePage { .. } => {}
+        }
+    }
+
+    /// Remap `from` to `to`, whose stroke has had `transform` applied to its points.
+    fn rebase(&mut self, from: Entity, to: Entity, transform: &Transform) {
+        self.remap(from, to);
+        if let Edit::Transform(strokes) = self
+            && *transform != Transform::IDENTITY
+        {
+            let inverse = Transform::from_matrix(transform.compute_matrix().inverse());
+            for (_, before, after) in strokes.iter_mut().filter(|x| x.0 == to) {
+                *before = before.mul_transform(inverse);
+                *after = after.mul_transform(inverse);
+            }
+        }
+    }
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct RecordEdit(pub Edit);
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    /// Forget all edits, as when strokes are replaced wholesale.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Record `edit` as done, which forgets the edits undone.
+    pub fn record(&mut self, edit: Edit) {
+        self.undo.push(edit);
+        self.redo.clear();
+        let overflow = self.undo.len().saturating_sub(MAX_HISTORY);
+        self.undo.drain(..overflow);
+    }
+
+    /// Record `edit` as undone, to be redone next.
+    pub fn record_undone(&mut self, edit: Edit) {
+        self.redo.push(edit);
+    }
+
+    /// Record `edit` as redone, to be undone next. Unlike [`History::record`], the edits left to
+    /// redo are kept.
+    pub fn record_redone(&mut self, edit: Edit) {
+        self.undo.push(edit);
+        let overflow = self.undo.len().saturating_sub(MAX_HISTORY);
+        self.undo.drain(..overflow);
+    }
+
+    /// Store the history of a page being left, where `strokes` are on screen.
+    pub fn store(
+        &mut self,
+        strokes: impl IntoIterator<Item = (Id, Entity, Transform)>,
+    ) -> StoredHistory {
+        StoredHistory {
+            history: std::mem::take(self),
+            strokes: strokes
+                .into_iter()
+                .map(|(id, entity, transform)| (id, (entity, transform)))
+                .collect(),
+        }
+    }
+
+    /// History of a page shown again, its strokes spawned anew as `spawned` with their transforms
+    /// applied.
+    pub fn restore(stored: StoredHistory, spawned: impl IntoIterator<Item = (Id, Entity)>) -> Self {
+        let StoredHistory {
+            mut history,
+            strokes,
+        } = stored;
+        for (id, to) in spawned {
+            if let Some((from, transform)) = strokes.get(&id) {
+                history
+                    .undo
+                    .iter_mut()
+                    .chain(history.redo.iter_mut())
+                    .for_each(|x| x.rebase(*from, to, transform));
+            }
+        }
+        history
+    }
+
+    fn remap(&mut self, from: Entity, to: Entity) {
+        self.undo
+            .iter_mut()
+            .chain(self.redo.iter_mut())
+            .for_each(|x| x.remap(from, to));
+    }
+}
+
+/// History of a page not shown, with the entity and transform each of its strokes had on screen.
+#[derive(Debug, Clone, Default)]
+pub struct StoredHistory {
+    history: History,
+    strokes: HashMap<Id, (Entity, Transform)>,
+}
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RecordEdit>()
+            .add_event::<HistoryEvent>()
+            .init_resource::<History>()
+            .add_systems(Update, (record_edits, apply_history).chain());
+    }
+}
+
+fn record_edits(mut events: EventReader<RecordEdit>, mut history: ResMut<History>) {
+    for RecordEdit(edit) in events.read() {
+        history.record(edit.clone());
+    }
+}
+
+fn apply_history(
+    mut events: EventReader<HistoryEvent>,
+    mut history: ResMut<History>,
+    mut commands: Commands,
+    mut transforms: Query<&mut Transform, With<Curve>>,
+    mut pages: EventWriter<PageEvent>,
+) {
+    for event in events.read() {
+        let undo = matches!(event, HistoryEvent::Undo);
+        let Some(mut edit) = (if undo {
+            history.undo.pop()
+        } else {
+            history.redo.pop()
+        }) else {
+            info!("Nothing to {event:?}");
+            continue;
+        };
+
+        let mut remaps = vec![];
+        match &mut edit {
+            Edit::Replace { removed, added } => {
+                let (gone, back) = if undo {
+                    (added, removed)
+                } else {
+                    (removed, added)
+                };
+                for stroke in gone.iter() {
+                    if let Ok(mut entity) = commands.get_entity(stroke.entity) {
+                        entity.despawn();
+                    }
+                }
+                for stroke in back.iter_mut() {
+                    let entity = spawn_curve(&mut commands, stroke.curve.clone());
+                    commands.entity(entity).insert(stroke.transform);
+                    remaps.push((stroke.entity, entity));
+                    stroke.entity = entity;
+                }
+            }
+            Edit::Transform(strokes) => {
+                for (entity, before, after) in strokes.iter() {
+                    if let Ok(mut transform) = transforms.get_mut(*entity) {
+                        *transform = if undo { *before } else { *after };
+                    }
+                }
+            }
+            Edit::DeletePage {
+                name,
+                index,
+                canvas,
+                history: stored,
+            } => {
+                // The page switched to afterwards takes the inverse edit, into its own history.
+                let name = std::mem::take(name);
+                pages.write(if undo {
+                    PageEvent::Restore {
+                        name,
+                        index: *index,
+                        canvas: std::mem::take(canvas),
+                        history: std::mem::take(stored),
+                    }
+                } else {
+                    PageEvent::Redelete(name)
+                });
+                continue;
+            }
+        }
+
+        if undo {
+            history.redo.push(edit);
+        } else {
+            history.undo.push(edit);
+        }
+        for (from, to) in remaps {
+            history.remap(from, to);
+        }
+    }
+}
@@ -1,18 +1,98 @@
-use glam::{Vec2, Vec4};
+use bevy::{
+    color::Color,
+    ecs::{reflect::ReflectResource, resource::Resource},
+    input::touch::ForceTouch,
+    math::{Vec2, Vec4},
+    reflect::Reflect,
+};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// Thinnest a stroke gets, as a fraction of brush width.
+const MIN_PRESSURE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct StrokePoint {
     pub position: Vec2,
     pub pressure: f32,
+    /// Milliseconds since 1970 when the point was read from input, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
 pub struct PenBrush {
+    /// sRGB color with alpha.
     pub color: Vec4,
     pub width: f32,
+    /// Multiplies alpha of `color`.
+    pub opacity: f32,
 }
 
 #[derive(Debug, Clone)]
 pub struct Stroke {
     pub points: Vec<StrokePoint>,
-}
\ No newline at end of file
+}
+
+/// The brush stamped onto new strokes.
+#[derive(Debug, Clone, Default, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ActiveBrush(pub PenBrush);
+
+impl StrokePoint {
+    pub fn new(position: Vec2, pressure: f32) -> Self {
+        Self {
+            position,
+            pressure,
+            time: None,
+        }
+    }
+}
+
+impl Default for PenBrush {
+    fn default() -> Self {
+        Self {
+            color: Vec4::ONE,
+            width: 3.0,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl PenBrush {
+    /// Color of the stroke, with opacity applied.
+    pub fn draw_color(&self) -> Color {
+        let [r, g, b, a] = self.color.to_array();
+        Color::srgba(r, g, b, a * self.opacity)
+    }
+
+    /// Width of the stroke where it is pressed with `pressure`.
+    pub fn width_at(&self, pressure: f32) -> f32 {
+        self.width * pressure.clamp(MIN_PRESSURE, 1.0)
+    }
+
+    /// Left and right edge of the stroke at `point`, heading to `direction`.
+    pub fn edges(&self, point: &StrokePoint, direction: Vec2) -> [Vec2; 2] {
+        let half_width = self.width_at(point.pressure) * 0.5;
+        let normal = direction.normalize_or_zero().perp() * half_width;
+        [point.position + normal, point.position - normal]
+    }
+}
+
+/// Pressure of a touch in `0..=1`. Touches without force, like fingers on most screens, press
+/// fully.
+pub fn touch_pressure(force: Option<ForceTouch>) -> f32 {
+    touch_force(force).unwrap_or(1.0)
+}
+
+/// Force of a touch in `0..=1`, if the screen measures it.
+pub fn touch_force(force: Option<ForceTouch>) -> Option<f32> {
+    match force {
+        Some(ForceTouch::Calibrated {
+            force,
+            max_possible_force,
+            ..
+        }) if max_possible_force > 0.0 => Some((force / max_possible_force) as f32),
+        Some(ForceTouch::Normalized(force)) => Some(force as f32),
+        _ => None,
+    }
+}
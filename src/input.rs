@@ -1 +1,278 @@
+use std::collections::HashMap;
 
+use bevy::{
+    ecs::system::SystemParam,
+    input::touch::{ForceTouch, TouchInput, TouchPhase},
+    prelude::*,
+};
+
+use crate::{
+    MousePosition, TouchMove,
+    args::{InkSource, PalmRejection},
+    camera::space_pan,
+    history::HistoryEvent,
+    ink::touch_force,
+    on_ui,
+};
+
+/// Longest a multi-finger tap may last, in seconds.
+const TAP_TIME: f64 = 0.3;
+/// Farthest a finger may travel in a tap, in logical pixels.
+const TAP_SLOP: f32 = 24.0;
+/// Whether a stylus is known before it is used. Only an Apple Pencil reports its tilt.
+const STYLUS_KNOWN: bool = cfg!(target_os = "ios");
+
+/// Tracks fingers down at once to recognize multi-finger taps: two fingers undo, three redo.
+#[derive(Resource, Debug, Default)]
+pub struct TapGesture {
+    /// Start position of each finger down.
+    touches: HashMap<u64, Vec2>,
+    /// Most fingers down since the first one.
+    most: usize,
+    /// Time of the first finger down.
+    start: f64,
+    moved: bool,
+}
+
+impl TapGesture {
+    /// Fingers down now.
+    pub fn fingers(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// Whether the fingers down at `now` may still make a tap, so they should not draw. Fingers
+    /// landing after the first one has been down for a while draw along with it.
+    pub fn may_tap(&self, now: f64) -> bool {
+        self.fingers() > 1 && now - self.start < TAP_TIME
+    }
+}
+
+/// What touches the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contact {
+    Finger,
+    Stylus,
+    Palm,
+}
+
+/// The [`Contact`] of each touch down.
+#[derive(Resource, Debug, Default)]
+pub struct TouchClasses {
+    /// Contact and start time of each touch.
+    contacts: HashMap<u64, (Contact, f64)>,
+    /// Whether a stylus has been used, for [`InkSource::Auto`].
+    stylus_seen: bool,
+}
+
+impl TouchClasses {
+    pub fn contact(&self, id: u64) -> Contact {
+        self.contacts.get(&id).map_or(Contact::Finger, |x| x.0)
+    }
+
+    /// Whether fingers draw under `policy`.
+    pub fn fingers_ink(&self, policy: &PalmRejection) -> bool {
+        match policy.ink {
+            InkSource::Any => true,
+            // Without a stylus to tell apart, any touch draws.
+            InkSource::Stylus => !STYLUS_KNOWN && !self.stylus_seen,
+            InkSource::Auto => !self.stylus_seen,
+        }
+    }
+
+    /// Whether touch `id` draws under `policy`.
+    pub fn inks(&self, id: u64, policy: &PalmRejection) -> bool {
+        match self.contact(id) {
+            Contact::Stylus => true,
+            Contact::Finger => self.fingers_ink(policy),
+            Contact::Palm => false,
+        }
+    }
+}
+
+/// Which new touches draw: inking ones, unless fingers down may be tapping.
+#[derive(SystemParam)]
+pub(crate) struct InkingTouches<'w> {
+    classes: Res<'w, TouchClasses>,
+    policy: Res<'w, PalmRejection>,
+    gesture: Res<'w, TapGesture>,
+    time: Res<'w, Time>,
+}
+
+impl InkingTouches<'_> {
+    /// Whether the touch `id` starts a stroke.
+    pub fn draws(&self, id: u64) -> bool {
+        self.classes.inks(id, &self.policy) && !self.gesture.may_tap(self.time.elapsed_secs_f64())
+    }
+}
+
+/// The pointer of tools which drag over strokes: the left mouse button, or a single inking touch.
+#[derive(SystemParam)]
+pub(crate) struct ToolPointer<'w, 's> {
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    keyboard: Res<'w, ButtonInput<KeyCode>>,
+    mouse_position: Res<'w, MousePosition>,
+    touches: Res<'w, Touches>,
+    classes: Res<'w, TouchClasses>,
+    policy: Res<'w, PalmRejection>,
+    interactions: Query<'w, 's, &'static Interaction>,
+}
+
+impl ToolPointer<'_, '_> {
+    /// Where the pointer is pressed on screen. Drags start off the UI, and may go over it once
+    /// `dragging`.
+    pub fn pressed(&self, dragging: bool) -> Option<Vec2> {
+        // Touches which do not draw, or two fingers at once, pan instead.
+        let inking: Vec<Vec2> = self
+            .touches
+            .iter()
+            .filter(|x| self.classes.inks(x.id(), &self.policy))
+            .map(|x| x.position())
+            .collect();
+        let pointer = if self.mouse.pressed(MouseButton::Left) && !space_pan(&self.keyboard) {
+            self.mouse_position.0
+        } else if let [position] = inking[..] {
+            Some(position)
+        } else {
+            None
+        };
+        pointer.filter(|_| dragging || !on_ui(&self.interactions))
+    }
+}
+
+/// Only an Apple Pencil reports its tilt.
+fn is_stylus(force: Option<ForceTouch>) -> bool {
+    matches!(
+        force,
+        Some(ForceTouch::Calibrated {
+            altitude_angle: Some(_),
+            ..
+        })
+    )
+}
+
+/// Classify new touches, and cancel strokes of touches found to be palms.
+pub(crate) fn classify_touches(
+    time: Res<Time>,
+    mut touch_events: EventReader<TouchInput>,
+    policy: Res<PalmRejection>,
+    mut classes: ResMut<TouchClasses>,
+    mut commands: Commands,
+    mut touch_state: ResMut<TouchMove>,
+) {
+    let now = time.elapsed_secs_f64();
+    let pressed_hard = |force: Option<ForceTouch>| {
+        policy.enabled
+            && policy
+                .max_force
+                .is_some_and(|max| touch_force(force) > Some(max))
+    };
+    let mut palms = vec![];
+    for touch_event in touch_events.read() {
+        match touch_event.phase {
+            TouchPhase::Started => {
+                let writing = classes.contacts.values().any(|x| x.0 == Contact::Stylus);
+                let contact = if is_stylus(touch_event.force) {
+                    Contact::Stylus
+                } else if pressed_hard(touch_event.force) || (policy.enabled && writing) {
+                    // The hand rests on the screen while writing.
+                    Contact::Palm
+                } else {
+                    Contact::Finger
+                };
+                if contact == Contact::Stylus {
+                    classes.stylus_seen = true;
+                    if policy.enabled {
+                        // The palm often lands just before the stylus.
+                        for (id, (contact, start)) in classes.contacts.iter_mut() {
+                            if *contact == Contact::Finger && now - *start < policy.window as f64 {
+                                *contact = Contact::Palm;
+                                palms.push(*id);
+                            }
+                        }
+                    }
+                }
+                classes.contacts.insert(touch_event.id, (contact, now));
+            }
+            TouchPhase::Moved => {
+                // A palm presses harder as it lands.
+                if pressed_hard(touch_event.force)
+                    && let Some((contact, _)) = classes.contacts.get_mut(&touch_event.id)
+                    && *contact == Contact::Finger
+                {
+                    *contact = Contact::Palm;
+                    palms.push(touch_event.id);
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Canceled => {
+                classes.contacts.remove(&touch_event.id);
+            }
+        }
+    }
+    for id in palms {
+        if let Some(entity) = touch_state.strokes.remove(&id) {
+            debug!("Rejected palm {id}");
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub(crate) fn detect_tap_gesture(
+    time: Res<Time>,
+    mut touch_events: EventReader<TouchInput>,
+    mut gesture: ResMut<TapGesture>,
+    mut history: EventWriter<HistoryEvent>,
+    mut commands: Commands,
+    mut touch_state: ResMut<TouchMove>,
+    classes: Res<TouchClasses>,
+) {
+    let now = time.elapsed_secs_f64();
+    for touch_event in touch_events.read() {
+        match touch_event.phase {
+            TouchPhase::Started => {
+                // Only fingers tap.
+                if classes.contact(touch_event.id) != Contact::Finger {
+                    continue;
+                }
+                if gesture.touches.is_empty() {
+                    gesture.start = now;
+                    gesture.most = 0;
+                    gesture.moved = false;
+                }
+                gesture.touches.insert(touch_event.id, touch_event.position);
+                gesture.most = gesture.most.max(gesture.touches.len());
+                if gesture.touches.len() == 2 && gesture.may_tap(now) {
+                    // The first finger was not drawing after all.
+                    let first = gesture.touches.keys().find(|x| **x != touch_event.id);
+                    if let Some(entity) = first.and_then(|x| touch_state.strokes.remove(x)) {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
+            TouchPhase::Moved => {
+                if let Some(start) = gesture.touches.get(&touch_event.id)
+                    && start.distance(touch_event.position) > TAP_SLOP
+                {
+                    gesture.moved = true;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Canceled => {
+                if gesture.touches.remove(&touch_event.id).is_none() || !gesture.touches.is_empty()
+                {
+                    continue;
+                }
+                if gesture.moved || now - gesture.start > TAP_TIME {
+                    continue;
+                }
+                match gesture.most {
+                    2 => {
+                        history.write(HistoryEvent::Undo);
+                    }
+                    3 => {
+                        history.write(HistoryEvent::Redo);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
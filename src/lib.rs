@@ -1,12 +1,29 @@
 // From demo.
 pub mod args;
+pub mod camera;
+pub mod clipboard;
+pub mod eraser;
+pub mod export;
+pub mod format;
+pub mod history;
+pub mod ink;
 pub mod input;
+pub mod merge;
+pub mod network;
+pub mod pages;
+pub mod playback;
+pub mod selection;
+pub mod shape;
+pub mod stabilizer;
 pub mod storage;
 pub mod stroke;
 pub mod ui;
 
-use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{collections::HashMap, time::Duration};
 
+use args::{AAMode, Args, Tuning};
 use bevy::{
     app::{App, Startup, Update},
     asset::RenderAssetUsages,
@@ -17,16 +34,20 @@ use bevy::{
     },
     core_pipeline::{fxaa::Fxaa, smaa::Smaa},
     diagnostic::{DiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
-    ecs::system::Commands,
+    ecs::system::{Commands, SystemParam},
     gizmos::gizmos::Gizmos,
     input::{ButtonState, mouse::MouseButtonInput, touch::TouchPhase},
     math::{cubic_splines::*, vec2},
     pbr::PbrPlugin,
     prelude::*,
-    render::mesh::{Indices, VertexAttributeValues},
+    render::{
+        RenderPlugin,
+        mesh::{Indices, VertexAttributeValues},
+        primitives::Aabb,
+        view::{NoCpuCulling, NoFrustumCulling, VisibilitySystems},
+    },
     scene::ScenePlugin,
     sprite::SpritePlugin,
-    winit::WinitSettings,
 };
 use bevy_dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin};
 #[cfg(feature = "inspect")]
@@ -37,106 +58,223 @@ use bevy_inspector_egui::{bevy_egui::EguiPlugin, prelude::*, quick::WorldInspect
 use bevy_pkv::PkvStore;
 #[cfg(not(target_arch = "wasm32"))]
 use bevy_render::pipelined_rendering::PipelinedRenderingPlugin;
+use camera::{MeshDetail, PanZoomPlugin, space_pan};
+use clipboard::{ClipboardEvent, ClipboardPlugin};
+use eraser::EraserPlugin;
+use export::{ExportEvent, ExportFormat, ExportOptions, ExportPlugin, png::BASE_DPI};
+use history::{Edit, HistoryEvent, HistoryPlugin, RecordEdit, StrokeRecord};
+use ink::{ActiveBrush, PenBrush, StrokePoint, touch_pressure};
+use input::{InkingTouches, TapGesture, TouchClasses, classify_touches, detect_tap_gesture};
+use merge::{Id, Stamp, now_millis, stamp_moves};
+use network::NetworkPlugin;
+use pages::{Notebook, PageEvent, PagesPlugin, Renaming, not_renaming};
+use playback::{PlaybackEvent, PlaybackPlugin, not_replaying};
+use selection::{SelectionEvent, SelectionPlugin};
 use serde::{Deserialize, Serialize};
+use shape::{HOLD_TIME, LastMoved, Shape, ShapeKind, recognize, track_hold};
+use stabilizer::{Stabilizer, stabilize};
+use storage::{MAIN_CANVAS, ProjectPath, StorageEvent, StoragePlugin};
+use ui::{OverlayPlugin, OverlayState, PalettePlugin, ToolbarPlugin};
 
 const VERTEX_BUFFER_SIZE: usize = 4096;
 
 #[bevy_main]
 pub fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        present_mode: bevy::window::PresentMode::AutoNoVsync,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .disable::<bevy::pbr::PbrPlugin>()
-                .disable::<bevy::animation::AnimationPlugin>()
-                .disable::<bevy::gltf::GltfPlugin>()
-                .disable::<bevy::audio::AudioPlugin>(),
-                //.disable::<bevy::render::RenderPlugin>(),
-                //.disable::<PipelinedRenderingPlugin>(), //.disable::<PbrPlugin>()
-                //.disable::<AudioPlugin>()
-                //.disable::<AnimationPlugin>()
-                //.disable::<ScenePlugin>()
-                //.disable::<DiagnosticsPlugin>()
-            bevy_mod_debugdump::CommandLineArgs,
-            #[cfg(feature = "diagnostic")]
-            LogDiagnosticsPlugin::default(),
-            #[cfg(feature = "diagnostic")]
-            FrameTimeDiagnosticsPlugin::default(),
-            #[cfg(feature = "diagnostic")]
-            bevy::diagnostic::EntityCountDiagnosticsPlugin,
-            #[cfg(feature = "diagnostic")]
-            bevy::diagnostic::SystemInformationDiagnosticsPlugin,
-            #[cfg(feature = "diagnostic")]
-            bevy_render::diagnostic::RenderDiagnosticsPlugin,
-            #[cfg(feature = "inspect")]
-            EguiPlugin::default(),
-            #[cfg(feature = "inspect")]
-            WorldInspectorPlugin::new(),
-            FpsOverlayPlugin {
-                config: FpsOverlayConfig {
-                    text_config: TextFont {
-                        // Here we define size of our overlay
-                        font_size: 24.0,
-                        // If we want, we can use a custom font
-                        font: default(),
-                        // We could also disable font smoothing,
-                        font_smoothing: bevy::text::FontSmoothing::AntiAliased,
-                        ..default()
-                    },
-                    // We can also change color of the overlay
-                    text_color: Color::Srgba(Srgba {
-                        red: 0.,
-                        green: 1.,
-                        blue: 0.,
-                        alpha: 1.,
-                    }),
-                    // We can also set the refresh interval for the FPS counter
-                    refresh_interval: core::time::Duration::from_millis(250),
-                    enabled: true,
+    let args = Args::from_env();
+    let config = args.config.clone().or_else(Tuning::path);
+    let mut tuning = Tuning::from_config(config.as_deref());
+    args.apply(&mut tuning);
+    if args.print_config {
+        args.print_config(&tuning, config.as_deref());
+        return;
+    }
+    if let Some(output) = &args.export {
+        let Some(project) = &args.project else {
+            eprintln!("metawrite: --export needs a PROJECT to export");
+            std::process::exit(2);
+        };
+        let options = ExportOptions {
+            background: Some(ClearColor::default().0),
+            dpi: args.dpi.unwrap_or(BASE_DPI),
+            bounds: args.bounds,
+        };
+        if let Err(e) = export::export_file(project, output, &options) {
+            eprintln!("metawrite: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let default_plugins = DefaultPlugins
+        .set(WindowPlugin {
+            primary_window: Some(Window {
+                present_mode: tuning.present_mode(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .set(RenderPlugin {
+            render_creation: tuning.wgpu_settings().into(),
+            ..Default::default()
+        })
+        .set(TaskPoolPlugin {
+            task_pool_options: tuning.task_pool_options(),
+        })
+        .set(tuning.image_plugin())
+        .disable::<bevy::pbr::PbrPlugin>()
+        .disable::<bevy::animation::AnimationPlugin>()
+        .disable::<bevy::gltf::GltfPlugin>()
+        .disable::<bevy::audio::AudioPlugin>();
+    //.disable::<bevy::render::RenderPlugin>(),
+    //.disable::<PipelinedRenderingPlugin>(), //.disable::<PbrPlugin>()
+    //.disable::<AudioPlugin>()
+    //.disable::<AnimationPlugin>()
+    //.disable::<ScenePlugin>()
+    //.disable::<DiagnosticsPlugin>()
+    #[cfg(not(target_arch = "wasm32"))]
+    let default_plugins = if tuning.pipelined {
+        default_plugins
+    } else {
+        default_plugins.disable::<PipelinedRenderingPlugin>()
+    };
+
+    let mut app = App::new();
+    app.add_plugins((
+        default_plugins,
+        bevy_mod_debugdump::CommandLineArgs,
+        #[cfg(feature = "diagnostic")]
+        LogDiagnosticsPlugin::default(),
+        #[cfg(feature = "diagnostic")]
+        FrameTimeDiagnosticsPlugin::default(),
+        #[cfg(feature = "diagnostic")]
+        bevy::diagnostic::EntityCountDiagnosticsPlugin,
+        #[cfg(feature = "diagnostic")]
+        bevy::diagnostic::SystemInformationDiagnosticsPlugin,
+        #[cfg(feature = "diagnostic")]
+        bevy_render::diagnostic::RenderDiagnosticsPlugin,
+        #[cfg(feature = "inspect")]
+        EguiPlugin::default(),
+        #[cfg(feature = "inspect")]
+        WorldInspectorPlugin::new(),
+        FpsOverlayPlugin {
+            config: FpsOverlayConfig {
+                text_config: TextFont {
+                    // Here we define size of our overlay
+                    font_size: 24.0,
+                    // If we want, we can use a custom font
+                    font: default(),
+                    // We could also disable font smoothing,
+                    font_smoothing: bevy::text::FontSmoothing::AntiAliased,
+                    ..default()
                 },
+                // We can also change color of the overlay
+                text_color: Color::Srgba(Srgba {
+                    red: 0.,
+                    green: 1.,
+                    blue: 0.,
+                    alpha: 1.,
+                }),
+                // We can also set the refresh interval for the FPS counter
+                refresh_interval: core::time::Duration::from_millis(250),
+                enabled: true,
             },
-        ))
-        .insert_resource(WinitSettings::desktop_app())
-        .add_systems(Startup, setup)
-        .add_systems(
-            PreUpdate,
-            (
-                handle_keypress,
-                handle_mouse_move,
-                handle_touch_state,
-                handle_mouse_press,
-            )
-                .chain(),
-        )
-        .add_systems(
-            Update,
-            (
-                //draw_edit_move,
-                //update_spline_mode_text,
-                //update_cycling_mode_text,
-                //curve_fill,
-                draw_curve,
-                //draw_control_points,
-                handle_button,
-            ),
+        },
+    ))
+    .add_plugins((
+        OverlayPlugin,
+        PalettePlugin,
+        ToolbarPlugin,
+        StoragePlugin,
+        HistoryPlugin,
+        EraserPlugin,
+        PanZoomPlugin,
+        ExportPlugin,
+        PagesPlugin,
+        SelectionPlugin,
+        ClipboardPlugin,
+        NetworkPlugin,
+        PlaybackPlugin,
+    ))
+    .add_event::<StrokeFinished>()
+    .init_resource::<TapGesture>()
+    .init_resource::<TouchClasses>()
+    .insert_resource(tuning.palm_rejection.clone())
+    .init_resource::<Tool>()
+    .insert_resource(tuning.winit_settings())
+    .insert_resource(ProjectPath(args.project))
+    .add_systems(Startup, setup)
+    .add_systems(
+        PreUpdate,
+        (
+            handle_keypress.run_if(not_renaming),
+            classify_touches,
+            detect_tap_gesture,
+            handle_mouse_move,
+            handle_touch_state,
+            handle_mouse_press,
+            time_input,
+            track_hold,
+            stabilize,
+            finish_stroke,
         )
-        .register_type::<Curve>()
-        .register_type::<CurrentCurve>()
-        .register_type::<MouseEditMove>()
-        .register_type::<MousePosition>()
-        .register_type::<TouchMove>()
-        .register_type::<CurrentCurveMarker>()
-        .register_type::<CurveMeshInfo>()
-        .run();
+            .chain()
+            .after(bevy::ui::UiSystem::Focus)
+            .run_if(in_state(OverlayState::Normal))
+            .run_if(not_replaying),
+    )
+    .add_systems(
+        Update,
+        (
+            //draw_edit_move,
+            update_spline_mode_text,
+            update_cycling_mode_text,
+            //curve_fill,
+            draw_curve,
+            //draw_control_points,
+            handle_button.run_if(not_replaying),
+        ),
+    )
+    .add_systems(
+        PostUpdate,
+        (
+            mesh_curves.before(VisibilitySystems::CalculateBounds),
+            stamp_moves,
+        ),
+    )
+    .register_type::<Curve>()
+    .register_type::<CurrentCurve>()
+    .register_type::<MouseEditMove>()
+    .register_type::<MousePosition>()
+    .register_type::<TouchMove>()
+    .register_type::<CurrentCurveMarker>()
+    .register_type::<CurveMeshInfo>()
+    .register_type::<ActiveBrush>()
+    .register_type::<Tool>();
+    if let Some(collab) = args.collab {
+        app.insert_resource(collab);
+    }
+    if tuning.diagnostic && !app.is_plugin_added::<LogDiagnosticsPlugin>() {
+        app.add_plugins(LogDiagnosticsPlugin::default());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_systems(Last, limit_fps);
+    app.insert_resource(tuning).run();
 }
 
-fn setup(mut commands: Commands) {
+/// Sleep out the rest of the frame, so frames come no faster than [`Tuning::fps_limit`].
+#[cfg(not(target_arch = "wasm32"))]
+fn limit_fps(tuning: Res<Tuning>, mut frame_start: Local<Option<Instant>>) {
+    let Some(limit) = tuning.fps_limit.filter(|x| *x > 0.0) else {
+        return;
+    };
+    let frame = Duration::from_secs_f32(1.0 / limit);
+    if let Some(elapsed) = frame_start.map(|x| x.elapsed()) {
+        std::thread::sleep(frame.saturating_sub(elapsed));
+    }
+    *frame_start = Some(Instant::now());
+}
+
+fn setup(mut commands: Commands, tuning: Res<Tuning>) {
     // Initialize the modes with their defaults:
     let spline_mode = SplineMode::default();
     commands.insert_resource(spline_mode);
@@ -173,15 +311,36 @@ fn setup(mut commands: Commands) {
     commands.insert_resource(MousePosition::default());
     commands.insert_resource(MouseEditMove::default());
 
-    #[cfg(not(target_arch = "wasm32"))]
-    commands.spawn(Camera2d).insert(Msaa::Sample4);
-
-    #[cfg(target_arch = "wasm32")]
-    commands.spawn(Camera2d);
+    let mut camera = commands.spawn((Camera2d, tuning.msaa()));
+    match tuning.anti_aliasing {
+        AAMode::Fxaa => {
+            camera.insert(Fxaa::default());
+        }
+        AAMode::Smaa => {
+            camera.insert(Smaa::default());
+        }
+        _ => {}
+    }
+    if !tuning.frustum_culling {
+        camera.insert(NoCpuCulling);
+    }
 
     // The instructions and modes are rendered on the left-hand side in a column.
     let instructions_text = "Draw on the screen.\n\
-        R: Remove the last control point\n";
+        P: Pen, E: Eraser, Shift+E: Partial eraser\n\
+        Shift+P / hold at end of stroke: Shapes\n\
+        L: Select (Shift: rectangle), Delete: Remove selected\n\
+        Ctrl+C / Ctrl+X / Ctrl+V: Copy / Cut / Paste selected\n\
+        R / Ctrl+Z / two-finger tap: Undo\n\
+        Ctrl+Shift+Z / three-finger tap: Redo\n\
+        Space+drag / middle drag / two fingers: Pan\n\
+        Ctrl+wheel / pinch: Zoom\n\
+        Ctrl+S: Save, Ctrl+O: Load\n\
+        Ctrl+E: Export SVG, Ctrl+Shift+E: PDF, Ctrl+Alt+E: PNG\n\
+        PageUp/PageDown: Switch page, Ctrl+N: New page\n\
+        F2: Rename page, Ctrl+Shift+PageUp/PageDown: Move page\n\
+        Ctrl+Delete: Delete page (undo brings it back)\n\
+        T: Replay drawing (Space: Pause, arrows: Seek / Speed)\n";
     let spline_mode_text = format!("Spline: {spline_mode}");
     let cycling_mode_text = format!("{cycling_mode}");
     let style = TextFont::default();
@@ -197,8 +356,8 @@ fn setup(mut commands: Commands) {
         })
         .with_children(|parent| {
             parent.spawn((Text::new(instructions_text), style.clone()));
-            //parent.spawn((SplineModeText, Text(spline_mode_text), style.clone()));
-            //parent.spawn((CyclingModeText, Text(cycling_mode_text), style.clone()));
+            parent.spawn((SplineModeText, Text(spline_mode_text), style.clone()));
+            parent.spawn((CyclingModeText, Text(cycling_mode_text), style.clone()));
         });
 
     commands
@@ -224,7 +383,7 @@ fn setup(mut commands: Commands) {
 
 /// The current spline mode, which determines the spline method used in conjunction with the
 /// control points.
-#[derive(Clone, Copy, Resource, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource, Default, Reflect, Serialize, Deserialize)]
 enum SplineMode {
     Hermite,
     #[default]
@@ -244,7 +403,7 @@ impl std::fmt::Display for SplineMode {
 
 /// The current cycling mode, which determines whether the control points should be interpolated
 /// cyclically (to make a loop).
-#[derive(Clone, Copy, Resource, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource, Default, Reflect, Serialize, Deserialize)]
 enum CyclingMode {
     #[default]
     NotCyclic,
@@ -266,19 +425,91 @@ impl std::fmt::Display for CyclingMode {
 pub struct Curve {
     points: Vec<Vec2>,
     which: usize,
+    /// Pressure of each point, in `0..=1`.
+    #[serde(default)]
+    pressure: Vec<f32>,
+    #[serde(default)]
+    brush: PenBrush,
+    /// Spline through the points.
+    #[serde(default)]
+    spline: SplineMode,
+    /// Whether the finished curve closes into a loop.
+    #[serde(default)]
+    cycling: CyclingMode,
+    /// Shape the curve is drawn as, straight through its points rather than along the spline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shape: Option<ShapeKind>,
+    /// Identity of the stroke, which its pieces and copies do not share.
+    #[serde(default = "Id::new")]
+    id: Id,
+    /// When the stroke was drawn or last moved.
+    #[serde(default)]
+    stamp: Stamp,
+    /// Milliseconds since 1970 when the stroke was drawn, if known. Pieces of a stroke keep it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<u64>,
+    /// Milliseconds from `created` to when each point was drawn, or empty if not known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    times: Vec<u32>,
+}
+
+impl Curve {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            points: Vec::with_capacity(capacity),
+            which: 0,
+            pressure: Vec::with_capacity(capacity),
+            brush: PenBrush::default(),
+            spline: SplineMode::default(),
+            cycling: CyclingMode::default(),
+            shape: None,
+            id: Id::new(),
+            stamp: Stamp::now(),
+            created: Some(now_millis()),
+            times: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Identity of the stroke, kept across saves.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Milliseconds since 1970 when the stroke was drawn, if known.
+    pub fn created(&self) -> Option<u64> {
+        self.created
+    }
+
+    /// Whether the curve is a loop once finished. Loops need three points.
+    fn closed(&self) -> bool {
+        self.cycling == CyclingMode::Cyclic && self.points.len() >= 3
+    }
+
+    fn push(&mut self, point: StrokePoint) {
+        self.points.push(point.position);
+        self.pressure.push(point.pressure);
+        // Points not read from input, like the first, are timed as they reach the curve.
+        if let Some(created) = self.created
+            && self.times.len() + 1 == self.points.len()
+        {
+            let time = point
+                .time
+                .unwrap_or_else(now_millis)
+                .saturating_sub(created);
+            self.times.push(u32::try_from(time).unwrap_or(u32::MAX));
+        }
+    }
+
+    /// Pressure of point `i`. Points without pressure are pressed fully.
+    fn pressure_at(&self, i: usize) -> f32 {
+        self.pressure.get(i).copied().unwrap_or(1.0)
+    }
 }
 
 #[derive(Clone, Component, Reflect)]
 #[reflect(Component)]
 struct SplineCurve(CubicCurve<Vec2>);
 
-/// Curve timing annotation, use with `Curve`
-#[derive(Clone, Component, Default, Reflect)]
-#[reflect(Component)]
-struct CurveTiming {
-    strokes: Vec<(usize, usize, Duration)>,
-}
-
 #[derive(Clone, Component, Reflect)]
 #[reflect(Component)]
 struct ProcessedCurve {
@@ -307,14 +538,19 @@ struct CurrentCurve {
 #[derive(Clone, Component, Resource, Reflect)]
 #[reflect(Resource)]
 struct IncomingPoints {
-    points: Vec<Vec2>,
+    #[reflect(ignore)]
+    points: Vec<StrokePoint>,
 }
 
 #[derive(Clone, Component, Reflect)]
 #[reflect(Component)]
 struct CurveMeshInfo {
+    /// Vertices used in the mesh.
     used: usize,
+    /// Next spline segment to tessellate.
     current: usize,
+    /// [`MeshDetail`] the mesh is tessellated with.
+    detail: f32,
 }
 
 #[derive(Bundle)]
@@ -323,6 +559,10 @@ struct CurveMesh {
     info: CurveMeshInfo,
 }
 
+/// A stroke is done with input.
+#[derive(Debug, Clone, Copy, Event)]
+struct StrokeFinished(Entity);
+
 #[derive(Debug, Clone, Event, Component, Reflect)]
 struct PointEvent {
     pub points: Vec<Vec2>,
@@ -358,161 +598,112 @@ fn draw_curve(
         ),
         (Changed<IncomingPoints>, With<CurrentCurveMarker>),
     >,
-    //current: Res<CurrentCurve>,
     mut commands: Commands,
     mut meshs: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    detail: Res<MeshDetail>,
 ) {
-    // Scale resolution with curve length so it doesn't degrade as the length increases.
-
-    // Gizmos is not parallel.
-    //for curve in curves {
-    //let resolution = 100 * curve..segments().len();
-    //gizmos.linestrip(
-    //    curve.0.iter_positions(resolution).map(|pt| pt.extend(0.0)),
-    //    Color::srgb(1.0, 1.0, 1.0),
-    //);
-    //gizmos.curve_2d(
-    //    &curve.0,
-    //    (0..resolution).map(|n| n as f32 / 100.0),
-    //    Color::srgb(1.0, 1.0, 1.0),
-    //);
-    //gizmos.linestrip_2d(curve.interp.iter().map(|x| *x), Color::srgb(1.0, 1.0, 1.0));
-    //}
     curves.iter_mut().for_each(
         |(mut curve, mut incoming, entity, mesh2d, curve_mesh_info)| {
             if incoming.points.is_empty() {
                 return;
             }
-            // We should compute 4 points at a time.
-            curve.points.append(&mut incoming.points);
-            if curve.points.len() - curve.which <= 0 || curve.points.len() < 4 {
-                return;
-            };
-            //info!("incoming {} points", curve.points.len() - curve.which);
-            let full_line = curve.points.len() - curve.which >= 4;
-            let Some(spline) = form_curve(
-                &curve.points[if full_line {
-                    curve.which..
-                } else {
-                    (curve.points.len() - 4)..
-                }],
-                SplineMode::Cardinal,
-                CyclingMode::NotCyclic,
-            ) else {
-                warn!("Failed to form spline!");
-                return;
-            };
-            // Emit curve
-
-            let resolution =
-                calc_resolution(&curve.points[curve.which..]) * spline.segments().len();
-            //info!("resolution {}", resolution);
-            let points: Vec<_> = spline.iter_positions(resolution).collect();
-            let points = if full_line {
-                points
+            incoming
+                .points
+                .drain(..)
+                .for_each(|point| curve.push(point));
+
+            if let (Some(mesh2d), Some(mut mesh_info)) = (mesh2d, curve_mesh_info) {
+                // If a mesh already exists, append the new segments.
+                let Some(mesh) = meshs.get_mut(mesh2d.id()) else {
+                    return;
+                };
+                let (samples, next) =
+                    sample_curve(&curve, mesh_info.current, false, mesh_info.detail);
+                extend_curve_mesh(mesh, &mut mesh_info, &samples, &curve.brush);
+                mesh_info.current = next;
             } else {
-                points[((curve.points.len() - curve.which - 1) * resolution)..].to_owned()
-            };
-            //let mut mesh = Mesh::new(
-            //    bevy::render::render_resource::PrimitiveTopology::LineStrip,
-            //    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-            //);
-            //mesh.insert_attribute(
-            //    Mesh::ATTRIBUTE_POSITION,
-            //    points
-            //        .iter()
-            //        .map(|p| [p.x, p.y, 0.0])
-            //        .collect::<Vec<[f32; 3]>>(),
-            //);
-
-            ////par_cmd.command_scope(|mut commands| {
-            //commands.entity(entity).with_children(|parent| {
-            //    parent.spawn((
-            //        Mesh2d(meshs.add(mesh)),
-            //        MeshMaterial2d(materials.add(ColorMaterial::from(Color::WHITE))),
-            //    ));
-            //});
-            ////});
-            //curve.which = curve.points.len() - 1;
-            if let Some(mesh_entity) = mesh2d {
-                // If a mesh already exists, update its data
-                if let Some(mesh) = meshs.get_mut(mesh_entity.id()) {
-                    let mut mesh_info = curve_mesh_info.unwrap();
-                    let new_size = mesh_info.used + points.len();
-                    if let Some(VertexAttributeValues::Float32x3(positions)) =
-                        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
-                    {
-                        if positions.len() < new_size {
-                            positions.resize(new_size.max(positions.len() * 2), [0.0, 0.0, 0.0]);
-                            info!("mesh resized to {}", positions.len());
-                        }
-                        for (i, p) in points.iter().enumerate() {
-                            positions[mesh_info.used + i] = [p.x, p.y, 0.0];
-                        }
-                        //mesh.insert_indices(Indices::U32( (0..(mesh_info.used-1) as u32).collect() ));
-                    }
-                    if let Some(indices) = mesh.indices_mut() {
-                        match indices {
-                            Indices::U32(k) => {
-                                if k.len() < new_size {
-                                    k.reserve(new_size.max(k.len() * 2));
-                                }
-                                //for i in 0..(points.len()) {
-                                //    k[mesh_info.used + i] = (mesh_info.used + i) as u32;
-                                //}
-                                //k.resize(new_size, 0);
-                                k.extend(
-                                    (mesh_info.used as u32)
-                                        ..((mesh_info.used + points.len()) as u32),
-                                );
-                            }
-                            _ => {
-                                panic!("should be 32bit indice!");
-                            }
-                        }
-                    }
-                    mesh_info.used += points.len();
-                    curve.which = curve.points.len() - 1;
+                // If no mesh exists, create one once there is something to show.
+                let (samples, next) = sample_curve(&curve, 0, false, detail.0);
+                if samples.len() < 2 {
+                    return;
                 }
-            } else {
-                // If no mesh exists, create a new one as a child of the curve entity
-                //let mut mesh = Mesh::new(
-                //    bevy::render::render_resource::PrimitiveTopology::LineStrip,
-                //    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-                //);
                 let mut mesh = create_curve_mesh(VERTEX_BUFFER_SIZE);
-                if let Some(VertexAttributeValues::Float32x3(positions)) =
-                    mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
-                {
-                    for (i, p) in points.iter().enumerate() {
-                        positions[i] = [p.x, p.y, 0.0];
-                    }
-                }
-                let mut indices: Vec<_> = (0..(points.len() - 1) as u32).collect();
-                mesh.insert_indices(Indices::U32(indices));
+                let mut mesh_info = CurveMeshInfo {
+                    used: 0,
+                    current: next,
+                    detail: detail.0,
+                };
+                extend_curve_mesh(&mut mesh, &mut mesh_info, &samples, &curve.brush);
+                // The mesh grows past its bounds until the stroke is finished.
                 commands.entity(entity).insert((
                     Mesh2d(meshs.add(mesh)),
-                    MeshMaterial2d(materials.add(ColorMaterial::from(Color::WHITE))),
-                    CurveMeshInfo {
-                        used: points.len(),
-                        current: 0,
-                    },
+                    MeshMaterial2d(materials.add(ColorMaterial::from(curve.brush.draw_color()))),
+                    mesh_info,
+                    NoFrustumCulling,
                 ));
-                curve.which = curve.points.len() - 1;
             }
+            curve.which = curve.points.len() - 1;
         },
     );
-    // Form current curve. To be optimized.
-    //let Some(curve) = form_curve(&current, SplineMode::Cardinal, CyclingMode::NotCyclic) else {
-    //    return;
-    //};
-    //let resolution = 3 * curve.points.segments().len();
-    //gizmos.curve_2d(
-    //    &curve.points,
-    //    (0..resolution).map(|n| n as f32 / 3. as f32),
-    //    Color::srgb(1.0, 1.0, 1.0),
-    //);
+}
+
+/// Sample the spline of `curve` from segment `from` on, along with its direction at each sample.
+///
+/// Segments which still change when more points come are left out, unless the curve is
+/// `finished`. Loops are only closed once finished. Returns the samples and the next segment to
+/// sample.
+fn sample_curve(
+    curve: &Curve,
+    from: usize,
+    finished: bool,
+    detail: f32,
+) -> (Vec<(StrokePoint, Vec2)>, usize) {
+    let n = curve.points.len();
+    let cyclic = finished && curve.closed();
+    // A segment depends on one point before and two after its start.
+    let end = if cyclic {
+        n
+    } else if finished {
+        n.saturating_sub(1)
+    } else {
+        n.saturating_sub(2)
+    };
+    if from >= end {
+        return (vec![], from);
+    }
+    let (lo, cycling) = if cyclic {
+        (0, CyclingMode::Cyclic)
+    } else {
+        (from.saturating_sub(1), CyclingMode::NotCyclic)
+    };
+    let Some(spline) = form_curve(&curve.points[lo..(end + 2).min(n)], curve.spline, cycling)
+    else {
+        warn!("Failed to form spline!");
+        return (vec![], from);
+    };
+
+    let mut samples = vec![];
+    for k in from..end {
+        let segment = &spline.segments()[k - lo];
+        let next = (k + 1) % n;
+        let resolution = calc_resolution(&[curve.points[k], curve.points[next]], detail);
+        let (p0, p1) = (curve.pressure_at(k), curve.pressure_at(next));
+        // The end of a segment is the start of the next one.
+        let last = if finished && k + 1 == end {
+            resolution
+        } else {
+            resolution - 1
+        };
+        samples.extend((0..=last).map(|i| {
+            let t = i as f32 / resolution as f32;
+            (
+                StrokePoint::new(segment.position(t), p0.lerp(p1, t)),
+                segment.velocity(t),
+            )
+        }));
+    }
+    (samples, end)
 }
 
 /// This system uses gizmos to draw the current [control points] as circles, displaying their
@@ -531,44 +722,79 @@ fn draw_control_points(control_points: Res<CurrentCurve>, spline_mode: Res<Splin
 
 /// Helper function for generating a [`Curve`] from [control points] and selected modes.
 ///
+/// In every mode, segment `k` runs from point `k` (or near it, for B-splines) to point `k + 1`
+/// and depends on points `k - 1` to `k + 2`, so a curve can be formed over a window of its
+/// points. Open curves get a segment less than points, cyclic ones as many.
+///
 /// [control points]: ControlPoints
 fn form_curve(
     control_points: &[Vec2],
     spline_mode: SplineMode,
     cycling_mode: CyclingMode,
 ) -> Option<CubicCurve<Vec2>> {
-    //let (points, tangents): (Vec<_>, Vec<_>) =
-    //    control_points.iter().copied().unzip();
-
-    //match spline_mode {
-    //    SplineMode::Hermite => {
-    //        let spline = CubicHermite::new(points, tangents);
-    //        match cycling_mode {
-    //            CyclingMode::NotCyclic => spline.to_curve().ok(),
-    //            CyclingMode::Cyclic => spline.to_curve_cyclic().ok(),
-    //        }
-    //        .map(|x| SplineCurve(x))
-    //    }
-    //    SplineMode::Cardinal => {
-    //        let spline = CubicCardinalSpline::new_catmull_rom(points);
-    //        match cycling_mode {
-    //            CyclingMode::NotCyclic => spline.to_curve().ok(),
-    //            CyclingMode::Cyclic => spline.to_curve_cyclic().ok(),
-    //        }
-    //        .map(|x| SplineCurve(x))
-    //    }
-    //    SplineMode::B => {
-    //        let spline = CubicBSpline::new(points);
-    //        match cycling_mode {
-    //            CyclingMode::NotCyclic => spline.to_curve().ok(),
-    //            CyclingMode::Cyclic => spline.to_curve_cyclic().ok(),
-    //        }
-    //        .map(|x| SplineCurve(x))
-    //    }
-    //}
-    CubicCardinalSpline::new_catmull_rom(control_points.iter().copied())
-        .to_curve()
-        .ok()
+    let points = control_points.iter().copied();
+    let cyclic = cycling_mode == CyclingMode::Cyclic;
+    match spline_mode {
+        SplineMode::Hermite => {
+            let spline = CubicHermite::new(points, hermite_tangents(control_points, cyclic));
+            match cycling_mode {
+                CyclingMode::NotCyclic => spline.to_curve().ok(),
+                CyclingMode::Cyclic => spline.to_curve_cyclic().ok(),
+            }
+        }
+        SplineMode::Cardinal => {
+            let spline = CubicCardinalSpline::new_catmull_rom(points);
+            match cycling_mode {
+                CyclingMode::NotCyclic => spline.to_curve().ok(),
+                CyclingMode::Cyclic => spline.to_curve_cyclic().ok(),
+            }
+        }
+        SplineMode::B => {
+            let (&first, &last) = (control_points.first()?, control_points.last()?);
+            let n = control_points.len();
+            match cycling_mode {
+                // Without mirrored ends the curve would stop short of the end points.
+                CyclingMode::NotCyclic => CubicBSpline::new(
+                    std::iter::once(2.0 * first - control_points[1.min(n - 1)])
+                        .chain(points)
+                        .chain(std::iter::once(
+                            2.0 * last - control_points[n.saturating_sub(2)],
+                        )),
+                )
+                .to_curve()
+                .ok(),
+                // Rotated, as the first cyclic segment would start at the second point.
+                CyclingMode::Cyclic => CubicBSpline::new(
+                    std::iter::once(last).chain(control_points[..n - 1].iter().copied()),
+                )
+                .to_curve_cyclic()
+                .ok(),
+            }
+        }
+    }
+}
+
+/// Tangents of a Hermite spline through `points`. Each follows the neighbours of its point, as
+/// long as the shorter chord to them so unevenly spaced points do not overshoot.
+fn hermite_tangents(points: &[Vec2], cyclic: bool) -> Vec<Vec2> {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let (prev, next) = if cyclic {
+                (points[(i + n - 1) % n], points[(i + 1) % n])
+            } else {
+                (points[i.saturating_sub(1)], points[(i + 1).min(n - 1)])
+            };
+            let chords = [points[i].distance(prev), points[i].distance(next)];
+            // End points of open curves have a single chord.
+            let chord = chords
+                .into_iter()
+                .filter(|x| *x > 0.0)
+                .reduce(f32::min)
+                .unwrap_or(0.0);
+            (next - prev).normalize_or_zero() * chord
+        })
+        .collect()
 }
 
 fn curve_with_lyon(control_points: &CurrentCurve, mut commands: Commands) {}
@@ -631,10 +857,37 @@ struct MouseEditMove {
     start: Option<Vec2>,
 }
 
+/// Touches drawing now, each with the stroke it draws.
 #[derive(Clone, Default, Resource, Reflect)]
 #[reflect(Resource)]
 struct TouchMove {
-    which: Option<u64>,
+    strokes: HashMap<u64, Entity>,
+}
+
+/// What pointer input does on the canvas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource, Reflect)]
+#[reflect(Resource)]
+pub enum Tool {
+    #[default]
+    Pen,
+    /// Removes whole strokes it touches.
+    StrokeEraser,
+    /// Removes parts of strokes it passes over.
+    PartialEraser,
+    /// A pen whose strokes become the shapes they look like.
+    Shapes,
+    /// Picks strokes to move, scale and rotate.
+    Select,
+}
+
+impl Tool {
+    fn draws(&self) -> bool {
+        matches!(self, Tool::Pen | Tool::Shapes)
+    }
+
+    fn is_eraser(&self) -> bool {
+        matches!(self, Tool::StrokeEraser | Tool::PartialEraser)
+    }
 }
 
 /// The current mouse position, if known.
@@ -649,7 +902,7 @@ fn handle_mouse_move(
     mut mouse_position: ResMut<MousePosition>,
 
     mut target: Query<(&mut IncomingPoints, &CurrentCurveMarker, Entity)>,
-    touch_state: ResMut<TouchMove>,
+    touch_state: Res<TouchMove>,
     edit_move: Res<MouseEditMove>,
     camera: Single<(&Camera, &GlobalTransform)>,
 ) {
@@ -675,31 +928,48 @@ fn handle_mouse_move(
             .filter(|(_, marker, _)| matches!(marker, CurrentCurveMarker::Mouse))
             .next()
         {
-            points.points.push(current);
+            points.points.push(StrokePoint::new(current, 1.0));
         }
     }
 
     //debug!("Reading movements...");
     for touch_event in touch_events.read() {
         //debug!("touch {:?}", touch_event);
-        if Some(touch_event.id) == touch_state.which {
-            // Consider only the first touch (single touch)
-            mouse_position.bypass_change_detection().0 = Some(touch_event.position);
-            let Some(_) = edit_move.start else {
-                return;
-            };
-            let (camera, camera_transform) = *camera;
-            let Ok(current) = camera.viewport_to_world_2d(camera_transform, touch_event.position)
-            else {
-                return;
-            };
-            if let Some((mut points, marker, entity)) = target
-                .iter_mut()
-                .filter(|(_, marker, _)| matches!(marker, CurrentCurveMarker::Touch(0)))
-                .next()
-            {
-                points.points.push(current);
-            }
+        // Each touch feeds its own stroke.
+        let Some(&entity) = touch_state.strokes.get(&touch_event.id) else {
+            continue;
+        };
+        mouse_position.bypass_change_detection().0 = Some(touch_event.position);
+        let (camera, camera_transform) = *camera;
+        let Ok(current) = camera.viewport_to_world_2d(camera_transform, touch_event.position)
+        else {
+            continue;
+        };
+        if let Ok((mut points, _, _)) = target.get_mut(entity) {
+            points
+                .points
+                .push(StrokePoint::new(current, touch_pressure(touch_event.force)));
+        }
+    }
+}
+
+/// Time the points just read from input. Input events carry no time of their own, so the points
+/// of a stroke read in one frame are spread evenly over the time since the last frame.
+fn time_input(time: Res<Time<Real>>, mut strokes: Query<&mut IncomingPoints>) {
+    let now = now_millis();
+    let frame = time.delta().as_millis() as u64;
+    for mut incoming in strokes.iter_mut() {
+        if incoming.points.iter().all(|x| x.time.is_some()) {
+            continue;
+        }
+        let untimed: Vec<&mut StrokePoint> = incoming
+            .points
+            .iter_mut()
+            .filter(|x| x.time.is_none())
+            .collect();
+        let n = untimed.len() as u64;
+        for (i, point) in (1..).zip(untimed) {
+            point.time = Some(now - frame * (n - i) / n);
         }
     }
 }
@@ -713,9 +983,9 @@ fn handle_mouse_press(
     mut edit_move: ResMut<MouseEditMove>,
     mut current_strip: ResMut<CurrentCurve>,
     mut commands: Commands,
-    mut target: Query<(&mut IncomingPoints, &CurrentCurveMarker, Entity)>,
     //mut touch_state: ResMut<TouchMove>,
-    camera: Single<(&Camera, &GlobalTransform)>,
+    new_stroke: NewStroke,
+    mut mouse_stroke: MouseStroke,
 ) {
     let Some(mouse_pos) = mouse_position.0 else {
         return;
@@ -733,11 +1003,12 @@ fn handle_mouse_press(
                     // If the edit move already has a start, press event should do nothing.
                     continue;
                 }
+                if !new_stroke.allowed() || mouse_stroke.panning() {
+                    continue;
+                }
                 // This press represents the start of the edit move.
-                let (camera, camera_transform) = *camera;
                 edit_move.start = Some(mouse_pos);
-                let Ok(start_point) = camera.viewport_to_world_2d(camera_transform, mouse_pos)
-                else {
+                let Some(curve) = new_stroke.curve(mouse_pos, 1.0) else {
                     continue;
                 };
                 current_strip.points_and_tangents.clear();
@@ -745,14 +1016,12 @@ fn handle_mouse_press(
                 //    .points_and_tangents
                 //    .push((start_point, vec2(0., 0.)));
                 commands.spawn((
-                    Curve {
-                        points: vec![start_point],
-                        which: 0,
-                    },
+                    curve,
                     CurrentCurveMarker::Mouse,
                     IncomingPoints {
                         points: Vec::with_capacity(32),
                     },
+                    Stabilizer::default(),
                     Transform::default(),
                     Visibility::default(),
                 ));
@@ -764,13 +1033,11 @@ fn handle_mouse_press(
                     continue;
                 };
 
-                let (camera, camera_transform) = *camera;
-
                 // Convert the starting point and end point (current mouse pos) into world coords:
-                let Ok(point) = camera.viewport_to_world_2d(camera_transform, start) else {
+                let Some(point) = new_stroke.to_world(start) else {
                     continue;
                 };
-                let Ok(end_point) = camera.viewport_to_world_2d(camera_transform, mouse_pos) else {
+                let Some(end_point) = new_stroke.to_world(mouse_pos) else {
                     continue;
                 };
                 let tangent = end_point - point;
@@ -788,42 +1055,35 @@ fn handle_mouse_press(
                 //    commands.spawn((Curve(curve.0),));
                 //}
                 //current_strip.points_and_tangents.clear();
-                if let Some((mut points, marker, entity)) = target
-                    .iter_mut()
-                    .filter(|(_, marker, _)| matches!(marker, CurrentCurveMarker::Mouse))
-                    .next()
-                {
-                    commands.entity(entity).remove::<CurrentCurveMarker>();
-                    commands.entity(entity).remove::<IncomingPoints>();
-                }
+                mouse_stroke.finish();
             }
         }
     }
 }
 
-/// Handle touch/pen input.
+/// Handle touch/pen input. Every touch draws a stroke of its own, so several fingers can draw at
+/// once.
 fn handle_touch_state(
     mut touch_events: EventReader<TouchInput>,
-    mut edit_move: ResMut<MouseEditMove>,
     mut current_strip: ResMut<CurrentCurve>,
     mut commands: Commands,
     mut touch_state: ResMut<TouchMove>,
-    currents: Query<(&Curve, &mut ProcessedCurve, &CurrentCurveMarker, Entity)>,
-    camera: Single<(&Camera, &GlobalTransform)>,
+    new_stroke: NewStroke,
+    inking: InkingTouches,
+    mut finished: EventWriter<StrokeFinished>,
 ) {
     for touch_event in touch_events.read() {
-        // Consider only the first touch (single touch)
         debug!("Touch event {:?}", touch_event);
         match touch_event.phase {
             TouchPhase::Started => {
-                if touch_state.which.is_some() {
+                if touch_state.strokes.contains_key(&touch_event.id)
+                    || !inking.draws(touch_event.id)
+                    || !new_stroke.allowed()
+                {
                     continue;
                 }
-                let (camera, camera_transform) = *camera;
-                edit_move.start = Some(touch_event.position);
-                touch_state.which = Some(touch_event.id);
-                let Ok(start_point) =
-                    camera.viewport_to_world_2d(camera_transform, touch_event.position)
+                let Some(curve) =
+                    new_stroke.curve(touch_event.position, touch_pressure(touch_event.force))
                 else {
                     continue;
                 };
@@ -831,58 +1091,95 @@ fn handle_touch_state(
                 //current_strip
                 //    .points_and_tangents
                 //    .push((start_point, vec2(0., 0.)));
-                commands.spawn((
-                    Curve {
-                        points: Vec::with_capacity(VERTEX_BUFFER_SIZE),
-                        which: 0,
-                    },
-                    CurrentCurveMarker::Touch(0),
-                    IncomingPoints {
-                        points: Vec::with_capacity(32),
-                    },
-                    Transform::default(),
-                    Visibility::default(),
-                ));
+                let entity = commands
+                    .spawn((
+                        curve,
+                        CurrentCurveMarker::Touch(touch_event.id),
+                        IncomingPoints {
+                            points: Vec::with_capacity(32),
+                        },
+                        Stabilizer::default(),
+                        Transform::default(),
+                        Visibility::default(),
+                    ))
+                    .id();
+                touch_state.strokes.insert(touch_event.id, entity);
             }
-            TouchPhase::Ended => {
-                let Some(start) = edit_move.start else {
-                    continue;
-                };
+            TouchPhase::Ended | TouchPhase::Canceled => {
+                if let Some(entity) = touch_state.strokes.remove(&touch_event.id) {
+                    finished.write(StrokeFinished(entity));
+                }
+            }
+            _ => {} // Do nothing for Moved phase here
+        }
+    }
+}
 
-                let (camera, camera_transform) = *camera;
+/// What new strokes start with, and whether they may start.
+#[derive(SystemParam)]
+struct NewStroke<'w, 's> {
+    brush: Res<'w, ActiveBrush>,
+    spline_mode: Res<'w, SplineMode>,
+    cycling_mode: Res<'w, CyclingMode>,
+    tool: Res<'w, Tool>,
+    interactions: Query<'w, 's, &'static Interaction>,
+    camera: Single<'w, (&'static Camera, &'static GlobalTransform)>,
+}
 
-                let Ok(point) = camera.viewport_to_world_2d(camera_transform, start) else {
-                    continue;
-                };
-                let Ok(end_point) =
-                    camera.viewport_to_world_2d(camera_transform, touch_event.position)
-                else {
-                    continue;
-                };
-                let tangent = end_point - point;
+impl NewStroke<'_, '_> {
+    /// Whether the tool draws, and the pointer is off the UI.
+    fn allowed(&self) -> bool {
+        self.tool.draws() && !on_ui(&self.interactions)
+    }
 
-                edit_move.start = None;
-                touch_state.which = None;
+    /// Where `position` on screen is on the canvas.
+    fn to_world(&self, position: Vec2) -> Option<Vec2> {
+        let (camera, camera_transform) = *self.camera;
+        camera.viewport_to_world_2d(camera_transform, position).ok()
+    }
 
-                //let curve =
-                //    form_curve(&current_strip, SplineMode::Cardinal, CyclingMode::NotCyclic);
-                //if let Some(curve) = curve {
-                //    commands.spawn((Curve(curve.0),));
-                //}
-                //current_strip.points_and_tangents.clear();
-                if let Some((mut points, _, marker, entity)) = currents
-                    .iter()
-                    .filter(|(_, _, marker, _)| matches!(marker, CurrentCurveMarker::Touch(_)))
-                    .next()
-                {
-                    commands.entity(entity).remove::<CurrentCurveMarker>();
-                }
-            }
-            _ => {} // Do nothing for Moved or Cancelled phases here
+    /// A stroke with the active brush and modes, starting at `position` on screen.
+    fn curve(&self, position: Vec2, pressure: f32) -> Option<Curve> {
+        let mut curve = Curve::with_capacity(32);
+        curve.brush = self.brush.0.clone();
+        curve.spline = *self.spline_mode;
+        curve.cycling = *self.cycling_mode;
+        curve.push(StrokePoint::new(self.to_world(position)?, pressure));
+        Some(curve)
+    }
+}
+
+/// The stroke the mouse draws, and whether the mouse draws at all.
+#[derive(SystemParam)]
+struct MouseStroke<'w, 's> {
+    keyboard: Res<'w, ButtonInput<KeyCode>>,
+    target: Query<'w, 's, (Entity, &'static CurrentCurveMarker)>,
+    finished: EventWriter<'w, StrokeFinished>,
+}
+
+impl MouseStroke<'_, '_> {
+    /// Whether left drags pan the view instead of drawing.
+    fn panning(&self) -> bool {
+        space_pan(&self.keyboard)
+    }
+
+    /// Finish the stroke the mouse is drawing, if any.
+    fn finish(&mut self) {
+        if let Some((entity, _)) = self
+            .target
+            .iter()
+            .find(|(_, marker)| matches!(marker, CurrentCurveMarker::Mouse))
+        {
+            self.finished.write(StrokeFinished(entity));
         }
     }
 }
 
+/// Whether the pointer is over any UI node, where it should not draw.
+fn on_ui(interactions: &Query<&Interaction>) -> bool {
+    interactions.iter().any(|x| *x != Interaction::None)
+}
+
 /// This system handles drawing the "preview" control point based on the state of [`MouseEditMove`].
 fn draw_edit_move(
     edit_move: Res<MouseEditMove>,
@@ -913,12 +1210,100 @@ fn draw_edit_move(
     //gizmos.arrow_2d(start, end, Color::srgb(1.0, 0.0, 0.7));
 }
 
+/// What keyboard commands ask of other parts of the app.
+#[derive(SystemParam)]
+struct KeyCommands<'w> {
+    storage: EventWriter<'w, StorageEvent>,
+    history: EventWriter<'w, HistoryEvent>,
+    export: EventWriter<'w, ExportEvent>,
+    page: EventWriter<'w, PageEvent>,
+    selection: EventWriter<'w, SelectionEvent>,
+    clipboard: EventWriter<'w, ClipboardEvent>,
+    playback: EventWriter<'w, PlaybackEvent>,
+}
+
 /// This system handles all keyboard commands.
 fn handle_keypress(
     keyboard: Res<ButtonInput<KeyCode>>,
     mut spline_mode: ResMut<SplineMode>,
     mut cycling_mode: ResMut<CyclingMode>,
+    mut events: KeyCommands,
+    mut renaming: ResMut<Renaming>,
+    notebook: Res<Notebook>,
+    mut tool: ResMut<Tool>,
 ) {
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let alt = keyboard.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+
+    // Ctrl+S => save, Ctrl+O => load
+    // Ctrl+E => export SVG, Ctrl+Shift+E => export PDF, Ctrl+Alt+E => export PNG
+    // Ctrl+Z => undo, Ctrl+Shift+Z or Ctrl+Y => redo
+    // Ctrl+N => new page, Ctrl+Shift+PageUp/PageDown => move page, Ctrl+Delete => delete page
+    // Ctrl+C => copy, Ctrl+X => cut, Ctrl+V => paste
+    if ctrl {
+        if keyboard.just_pressed(KeyCode::KeyZ) {
+            events.history.write(if shift {
+                HistoryEvent::Redo
+            } else {
+                HistoryEvent::Undo
+            });
+        }
+        if keyboard.just_pressed(KeyCode::KeyY) {
+            events.history.write(HistoryEvent::Redo);
+        }
+        if keyboard.just_pressed(KeyCode::KeyS) {
+            events.storage.write(StorageEvent::Save);
+        }
+        if keyboard.just_pressed(KeyCode::KeyO) {
+            events.storage.write(StorageEvent::Load);
+        }
+        if keyboard.just_pressed(KeyCode::KeyE) {
+            events.export.write(ExportEvent(if shift {
+                ExportFormat::Pdf
+            } else if alt {
+                ExportFormat::Png
+            } else {
+                ExportFormat::Svg
+            }));
+        }
+        if keyboard.just_pressed(KeyCode::KeyN) {
+            events.page.write(PageEvent::New);
+        }
+        if shift && keyboard.just_pressed(KeyCode::PageUp) {
+            events.page.write(PageEvent::Move(-1));
+        }
+        if shift && keyboard.just_pressed(KeyCode::PageDown) {
+            events.page.write(PageEvent::Move(1));
+        }
+        if keyboard.just_pressed(KeyCode::Delete) {
+            events
+                .page
+                .write(PageEvent::Delete(notebook.active().to_owned()));
+        }
+        if keyboard.just_pressed(KeyCode::KeyC) {
+            events.clipboard.write(ClipboardEvent::Copy);
+        }
+        if keyboard.just_pressed(KeyCode::KeyX) {
+            events.clipboard.write(ClipboardEvent::Cut);
+        }
+        if keyboard.just_pressed(KeyCode::KeyV) {
+            events.clipboard.write(ClipboardEvent::Paste);
+        }
+        return;
+    }
+
+    // PageUp/PageDown => previous/next page, F2 => rename page
+    if keyboard.just_pressed(KeyCode::PageUp) {
+        events.page.write(PageEvent::Previous);
+    }
+    if keyboard.just_pressed(KeyCode::PageDown) {
+        events.page.write(PageEvent::Next);
+    }
+    if keyboard.just_pressed(KeyCode::F2) && notebook.active() != MAIN_CANVAS {
+        renaming.0 = Some(notebook.active().to_owned());
+    }
+
     // S => change spline mode
     if keyboard.just_pressed(KeyCode::KeyS) {
         *spline_mode = match *spline_mode {
@@ -936,7 +1321,38 @@ fn handle_keypress(
         }
     }
 
-    // R => remove last control point
+    // P => pen, Shift+P => shapes, E => eraser, Shift+E => partial eraser
+    if keyboard.just_pressed(KeyCode::KeyP) {
+        *tool = if shift { Tool::Shapes } else { Tool::Pen };
+    }
+    if keyboard.just_pressed(KeyCode::KeyE) {
+        *tool = if shift {
+            Tool::PartialEraser
+        } else {
+            Tool::StrokeEraser
+        };
+    }
+
+    // L => select, Delete => remove selected strokes, Escape => select nothing
+    if keyboard.just_pressed(KeyCode::KeyL) {
+        *tool = Tool::Select;
+    }
+    if keyboard.any_just_pressed([KeyCode::Delete, KeyCode::Backspace]) {
+        events.selection.write(SelectionEvent::Delete);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        events.selection.write(SelectionEvent::Clear);
+    }
+
+    // T => replay how the page was drawn
+    if keyboard.just_pressed(KeyCode::KeyT) {
+        events.playback.write(PlaybackEvent::Start);
+    }
+
+    // R => remove last stroke
+    if keyboard.just_pressed(KeyCode::KeyR) {
+        events.history.write(HistoryEvent::Undo);
+    }
 
     if keyboard.just_pressed(KeyCode::KeyQ) {
         std::process::exit(0);
@@ -958,18 +1374,150 @@ fn handle_keypress(
 //    });
 //}
 
+/// Create a ribbon mesh, with room for `capacity` vertices.
 fn create_curve_mesh(capacity: usize) -> Mesh {
     let mut mesh = Mesh::new(
-        bevy::render::render_resource::PrimitiveTopology::LineStrip,
+        bevy::render::render_resource::PrimitiveTopology::TriangleList,
         RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
     );
 
     let positions: Vec<[f32; 3]> = vec![[0.0, 0.0, 0.0]; capacity];
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_indices(Indices::U32(Vec::with_capacity(capacity * 3)));
 
     mesh
 }
 
+/// Append samples to a ribbon mesh. Each sample takes a pair of vertices on both edges of the
+/// stroke, and is joined to the pair before with two triangles.
+fn extend_curve_mesh(
+    mesh: &mut Mesh,
+    mesh_info: &mut CurveMeshInfo,
+    samples: &[(StrokePoint, Vec2)],
+    brush: &PenBrush,
+) {
+    let new_size = mesh_info.used + samples.len() * 2;
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        if positions.len() < new_size {
+            positions.resize(new_size.max(positions.len() * 2), [0.0, 0.0, 0.0]);
+            info!("mesh resized to {}", positions.len());
+        }
+        for (i, (point, direction)) in samples.iter().enumerate() {
+            let [left, right] = brush.edges(point, *direction);
+            positions[mesh_info.used + 2 * i] = [left.x, left.y, 0.0];
+            positions[mesh_info.used + 2 * i + 1] = [right.x, right.y, 0.0];
+        }
+    }
+    match mesh.indices_mut() {
+        Some(Indices::U32(indices)) => {
+            let first = (mesh_info.used as u32).max(2);
+            for pair in (first..new_size as u32).step_by(2) {
+                indices.extend([pair - 2, pair - 1, pair, pair - 1, pair + 1, pair]);
+            }
+        }
+        _ => {
+            panic!("should be 32bit indice!");
+        }
+    }
+    mesh_info.used = new_size;
+}
+
+/// Build the whole mesh of a finished curve, or of the shape it is drawn as.
+fn curve_mesh(curve: &Curve, detail: f32) -> Option<(Mesh, CurveMeshInfo)> {
+    if let Some(shape) = &curve.shape {
+        let mesh = shape.mesh(curve.brush.width_at(curve.pressure_at(0)), detail);
+        let mesh_info = CurveMeshInfo {
+            used: mesh.count_vertices(),
+            current: curve.points.len(),
+            detail,
+        };
+        return Some((mesh, mesh_info));
+    }
+    let (samples, next) = sample_curve(curve, 0, true, detail);
+    if samples.len() < 2 {
+        return None;
+    }
+    let mut mesh = create_curve_mesh(samples.len() * 2);
+    let mut mesh_info = CurveMeshInfo {
+        used: 0,
+        current: next,
+        detail,
+    };
+    extend_curve_mesh(&mut mesh, &mut mesh_info, &samples, &curve.brush);
+    Some((mesh, mesh_info))
+}
+
+/// Replace the mesh of a curve entity with one of the whole curve.
+fn set_curve_mesh(
+    commands: &mut Commands,
+    entity: Entity,
+    curve: &Curve,
+    mesh2d: Option<&Mesh2d>,
+    meshs: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    detail: f32,
+) {
+    let Some((mesh, mesh_info)) = curve_mesh(curve, detail) else {
+        return;
+    };
+    let mut entity = commands.entity(entity);
+    // Bounds are only computed once, so they are stale after the mesh grows.
+    entity
+        .insert(mesh_info)
+        .remove::<(Aabb, NoFrustumCulling)>();
+    match mesh2d.and_then(|x| meshs.get_mut(x.id())) {
+        Some(old) => *old = mesh,
+        None => {
+            entity.insert((
+                Mesh2d(meshs.add(mesh)),
+                MeshMaterial2d(materials.add(ColorMaterial::from(curve.brush.draw_color()))),
+            ));
+        }
+    }
+}
+
+/// Spawn a finished curve. It is meshed by [`mesh_curves`] before it is drawn.
+fn spawn_curve(commands: &mut Commands, mut curve: Curve) -> Entity {
+    curve.which = curve.points.len().saturating_sub(1);
+    commands
+        .spawn((curve, Transform::default(), Visibility::default()))
+        .id()
+}
+
+/// A curve with its mesh, if it has one yet.
+type MeshedCurve = (
+    Entity,
+    &'static Curve,
+    Option<&'static Mesh2d>,
+    Option<&'static CurveMeshInfo>,
+);
+
+/// Mesh finished curves which have no mesh yet, or one of another [`MeshDetail`].
+fn mesh_curves(
+    curves: Query<MeshedCurve, Without<CurrentCurveMarker>>,
+    detail: Res<MeshDetail>,
+    mut commands: Commands,
+    mut meshs: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (entity, curve, mesh2d, mesh_info) in curves.iter() {
+        if mesh_info.is_some_and(|x| x.detail == detail.0) {
+            continue;
+        }
+        set_curve_mesh(
+            &mut commands,
+            entity,
+            curve,
+            mesh2d,
+            &mut meshs,
+            &mut materials,
+            detail.0,
+        );
+    }
+}
+
 fn resize_curve_mesh(mesh: &mut Mesh, new_size: usize) {
     if let Some(VertexAttributeValues::Float32x3(positions)) =
         mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
@@ -980,11 +1528,11 @@ fn resize_curve_mesh(mesh: &mut Mesh, new_size: usize) {
     }
 }
 
-fn calc_resolution(points: &[Vec2]) -> usize {
+fn calc_resolution(points: &[Vec2], detail: f32) -> usize {
     points
         .iter()
         .zip(points[1..].iter())
-        .map(|(x, y)| x.distance(y.clone()).ceil() as usize)
+        .map(|(x, y)| (x.distance(y.clone()) * detail).ceil() as usize)
         .map(|x| x / 4)
         .max()
         .unwrap_or(1)
@@ -997,15 +1545,25 @@ struct ClearButton;
 
 fn handle_button(
     buttons: Query<&Interaction, (Changed<Interaction>, With<ClearButton>)>,
-    mut curves: Query<Entity, With<Curve>>,
+    curves: Query<(Entity, &Curve, &Transform), Without<CurrentCurveMarker>>,
     mut commands: Commands,
+    mut history: EventWriter<RecordEdit>,
 ) {
     for interaction in buttons {
         match *interaction {
             Interaction::Pressed => {
                 info!("Removed all curves");
-                for entity in curves.iter() {
+                let mut removed = vec![];
+                for (entity, curve, transform) in curves.iter() {
                     commands.entity(entity).despawn();
+                    removed.push(StrokeRecord {
+                        entity,
+                        curve: curve.clone(),
+                        transform: *transform,
+                    });
+                }
+                if !removed.is_empty() {
+                    history.write(RecordEdit(Edit::remove(removed)));
                 }
             }
             _ => {}
@@ -1019,4 +1577,144 @@ fn apply_draw_stroke(
 ) {
 }
 
-fn finish_stroke(mut target: Query<(&mut IncomingPoints, &CurrentCurveMarker, Entity)>) {}
+/// What finished strokes are kept as: shapes where asked for and found, or else simplified.
+#[derive(SystemParam)]
+struct StrokeCleanup<'w, 's> {
+    tool: Res<'w, Tool>,
+    tuning: Res<'w, Tuning>,
+    camera: Single<'w, &'static Projection, With<Camera>>,
+    moved: Query<'w, 's, &'static LastMoved>,
+    time: Res<'w, Time<Real>>,
+}
+
+impl StrokeCleanup<'_, '_> {
+    /// `curve` of the finished stroke `entity`, as kept.
+    fn clean(&self, entity: Entity, curve: &Curve) -> Curve {
+        // Tolerances are measured on screen, where the stroke was drawn.
+        let scale = match *self.camera {
+            Projection::Orthographic(ortho) => ortho.scale,
+            _ => 1.0,
+        };
+        // Resting at the end asks for a shape with any pen.
+        let held = self
+            .moved
+            .get(entity)
+            .is_ok_and(|x| self.time.elapsed().saturating_sub(x.at) >= HOLD_TIME);
+        let shape = (*self.tool == Tool::Shapes || held)
+            .then(|| recognize(&curve.points, scale))
+            .flatten();
+        match shape {
+            Some(kind) => Shape {
+                kind,
+                brush: curve.brush.clone(),
+                pressure: curve.pressure.iter().sum::<f32>() / curve.pressure.len().max(1) as f32,
+                id: curve.id,
+                stamp: curve.stamp,
+                created: curve.created,
+                duration: curve.times.last().copied(),
+            }
+            .to_curve(),
+            None => curve.simplify(self.tuning.simplify * scale),
+        }
+    }
+}
+
+/// Assets meshes of strokes are made of, and their detail.
+#[derive(SystemParam)]
+struct CurveMeshes<'w> {
+    meshs: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    detail: Res<'w, MeshDetail>,
+}
+
+/// Take the last points of finished strokes, simplify them, mesh them in full and record them.
+fn finish_stroke(
+    mut events: EventReader<StrokeFinished>,
+    mut target: Query<(&mut Curve, &mut IncomingPoints, &Transform, Option<&Mesh2d>)>,
+    cleanup: StrokeCleanup,
+    mut commands: Commands,
+    mut meshes: CurveMeshes,
+    mut history: EventWriter<RecordEdit>,
+) {
+    for StrokeFinished(entity) in events.read() {
+        let Ok((mut curve, mut incoming, transform, mesh2d)) = target.get_mut(*entity) else {
+            continue;
+        };
+        incoming
+            .points
+            .drain(..)
+            .for_each(|point| curve.push(point));
+        commands
+            .entity(*entity)
+            .remove::<(CurrentCurveMarker, IncomingPoints, LastMoved)>();
+        if curve.points.len() < 2 {
+            // Nothing to see.
+            commands.entity(*entity).despawn();
+            continue;
+        }
+        curve.which = curve.points.len() - 1;
+        *curve = cleanup.clean(*entity, &curve);
+        set_curve_mesh(
+            &mut commands,
+            *entity,
+            &curve,
+            mesh2d,
+            &mut meshes.meshs,
+            &mut meshes.materials,
+            meshes.detail.0,
+        );
+        history.write(RecordEdit(Edit::add(StrokeRecord {
+            entity: *entity,
+            curve: curve.clone(),
+            transform: *transform,
+        })));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn points_keep_their_input_time() {
+        let mut curve = Curve::with_capacity(2);
+        curve.created = Some(1000);
+        curve.push(StrokePoint {
+            time: Some(1250),
+            ..StrokePoint::new(Vec2::ZERO, 1.0)
+        });
+        curve.push(StrokePoint {
+            time: Some(900),
+            ..StrokePoint::new(Vec2::ONE, 1.0)
+        });
+        assert_eq!(curve.times, [250, 0]);
+    }
+
+    #[test]
+    fn input_is_spread_over_the_frame() {
+        let mut world = World::new();
+        let start = Instant::now();
+        let mut time = Time::<Real>::new(start);
+        // The first update only starts the clock.
+        time.update_with_instant(start);
+        time.update_with_instant(start + Duration::from_millis(30));
+        world.insert_resource(time);
+        let mut points = vec![StrokePoint::new(Vec2::ZERO, 1.0); 3];
+        points[0].time = Some(5);
+        let entity = world.spawn(IncomingPoints { points }).id();
+        world.run_system_once(time_input).unwrap();
+
+        let times: Vec<u64> = world.get::<IncomingPoints>(entity).unwrap().points[1..]
+            .iter()
+            .map(|x| x.time.unwrap())
+            .collect();
+        assert_eq!(
+            world.get::<IncomingPoints>(entity).unwrap().points[0].time,
+            Some(5)
+        );
+        assert_eq!(times[1] - times[0], 15);
+        assert!(times[1].abs_diff(now_millis()) < 1000);
+    }
+}
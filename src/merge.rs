@@ -0,0 +1,420 @@
+//! Merging copies of a project edited apart.
+//!
+//! Canvases are kept as a set of strokes where the last change wins: every stroke and element
+//! has an [`Id`] that stays with it, and a [`Stamp`] of when it was drawn or last moved. Removed
+//! strokes leave their id behind in [`Canvas::removed`], stamped with when they were removed.
+//! Merging two canvases keeps the latest version of each stroke, unless it was removed after
+//! that, so copies merge to the same strokes in whatever order they meet.
+//!
+//! Pages are merged by name. A page deleted from one copy comes back with the strokes of the
+//! other, and a renamed page is kept under both names.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    hash::{BuildHasher, Hasher, RandomState},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    CurrentCurveMarker, Curve,
+    storage::{Canvas, Elements, Project},
+};
+
+/// Crockford's base 32 digits, which ids are written in.
+const DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Bits of an id below its time.
+const RANDOM_BITS: u32 = 80;
+
+/// Identity of a stroke, unique across copies of a project.
+///
+/// Ids are ULIDs: milliseconds since 1970 above 80 random bits, written as 26 base 32 digits.
+/// Ids made later sort after those made earlier, so strokes sorted by id are in the order they
+/// were drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
+pub struct Id(u128);
+
+impl Id {
+    /// A new id, after all made so far.
+    pub fn new() -> Self {
+        static LAST: Mutex<u128> = Mutex::new(0);
+        let random = (u128::from(random()) << 64 | u128::from(random())) & ((1 << RANDOM_BITS) - 1);
+        let id = u128::from(now_millis()) << RANDOM_BITS | random;
+        let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+        // Within a millisecond, ids count up from the first.
+        *last = id.max(*last + 1);
+        Id(*last)
+    }
+
+    /// Id of `time` milliseconds and `rest` below, for ids made up from elsewhere.
+    pub(crate) fn from_parts(time: u64, rest: u128) -> Self {
+        Id(u128::from(time) << RANDOM_BITS | rest & ((1 << RANDOM_BITS) - 1))
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits: String = (0..26)
+            .rev()
+            .map(|i| DIGITS[(self.0 >> (i * 5)) as usize & 31] as char)
+            .collect();
+        f.write_str(&digits)
+    }
+}
+
+impl FromStr for Id {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 26 {
+            return Err(format!("id {s:?} is not 26 digits"));
+        }
+        s.bytes().try_fold(Id(0), |id, digit| {
+            let digit = digit.to_ascii_uppercase();
+            let value = DIGITS
+                .iter()
+                .position(|x| *x == digit)
+                .ok_or_else(|| format!("id {s:?} has a bad digit"))?;
+            Ok(Id(id.0 << 5 | value as u128))
+        })
+    }
+}
+
+impl Serialize for Id {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// When a stroke was last changed, and by which copy of the app. Later stamps win in merges.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    Reflect,
+)]
+pub struct Stamp {
+    /// Milliseconds since 1970.
+    time: u64,
+    /// Copy of the app, picked at random when it starts. Breaks ties.
+    replica: u64,
+}
+
+impl Stamp {
+    pub fn now() -> Self {
+        static REPLICA: OnceLock<u64> = OnceLock::new();
+        Self {
+            time: now_millis(),
+            replica: *REPLICA.get_or_init(random),
+        }
+    }
+}
+
+/// Milliseconds since 1970.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// A random number. The standard hasher's keys come from the system's entropy, and differ for
+/// each hasher.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// A stroke or element, as merged.
+enum Item<'a> {
+    Stroke(&'a Curve),
+    Element(&'a Elements),
+}
+
+impl Item<'_> {
+    fn version(&self) -> (Id, Stamp) {
+        match *self {
+            Item::Stroke(curve) | Item::Element(Elements::Curve(curve)) => (curve.id, curve.stamp),
+            Item::Element(Elements::Shape(shape)) => (shape.id, shape.stamp),
+            // Peeks are never changed.
+            Item::Element(Elements::Peek { id, .. }) => (*id, Stamp::default()),
+        }
+    }
+}
+
+impl Canvas {
+    /// Remove the stroke or element `id`, so it stays removed when merged. Returns whether it
+    /// was there.
+    pub fn remove(&mut self, id: Id) -> bool {
+        let before = self.strokes.len() + self.elements.len();
+        self.strokes.retain(|x| x.id != id);
+        self.elements.retain(|x| x.id() != id);
+        self.removed.insert(id, Stamp::now());
+        before != self.strokes.len() + self.elements.len()
+    }
+
+    /// Mark strokes in `before` but not in `now` as removed, and bring back those in `now`.
+    pub(crate) fn update_removed(&mut self, before: &HashSet<Id>, now: &HashSet<Id>) {
+        for id in before.difference(now) {
+            self.removed.entry(*id).or_insert_with(Stamp::now);
+        }
+        self.removed.retain(|id, _| !now.contains(id));
+    }
+}
+
+/// Both canvases in one, with the latest version of each stroke.
+pub fn merge_canvas(a: &Canvas, b: &Canvas) -> Canvas {
+    let mut removed = a.removed.clone();
+    for (id, stamp) in &b.removed {
+        let entry = removed.entry(*id).or_insert(*stamp);
+        *entry = (*entry).max(*stamp);
+    }
+    let mut latest: BTreeMap<Id, (Stamp, Item)> = BTreeMap::new();
+    for side in [a, b] {
+        let items = side
+            .strokes
+            .iter()
+            .map(Item::Stroke)
+            .chain(side.elements.iter().map(Item::Element));
+        for item in items {
+            let (id, stamp) = item.version();
+            if latest.get(&id).is_none_or(|x| x.0 < stamp) {
+                latest.insert(id, (stamp, item));
+            }
+        }
+    }
+    let mut canvas = Canvas::default();
+    for (id, (stamp, item)) in latest {
+        // A stroke changed after it was removed elsewhere stays.
+        if removed.get(&id).is_some_and(|x| *x >= stamp) {
+            continue;
+        }
+        match item {
+            Item::Stroke(curve) => canvas.strokes.push(curve.clone()),
+            Item::Element(element) => canvas.elements.push(element.clone()),
+        }
+    }
+    canvas.removed = removed;
+    canvas
+}
+
+/// Both projects in one. Canvases of the same name are merged, and pages only in `b` follow
+/// those of `a`. The title and info are those of `a`.
+///
+/// ```
+/// use metawrite::{
+///     merge::merge,
+///     storage::{Canvas, MAIN_CANVAS, Project},
+/// };
+///
+/// let stroke = |x: f32| {
+///     let json = format!(r#"{{"points": [[{x}, 0], [{x}, 10]], "which": 1}}"#);
+///     serde_json::from_str(&json).unwrap()
+/// };
+/// let project = Project::new("Notes", Canvas::new(vec![stroke(0.0), stroke(1.0)], vec![]));
+/// let ids = |project: &Project| -> Vec<_> {
+///     project.canvas[MAIN_CANVAS].curves().map(|x| x.id()).collect()
+/// };
+/// let first = ids(&project)[0];
+///
+/// // One copy gets a stroke while the other loses one.
+/// let (mut a, mut b) = (project.clone(), project);
+/// a.canvas.get_mut(MAIN_CANVAS).unwrap().strokes.push(stroke(2.0));
+/// b.canvas.get_mut(MAIN_CANVAS).unwrap().remove(first);
+///
+/// let merged = merge(&a, &b);
+/// assert_eq!(ids(&merged).len(), 2);
+/// assert!(!ids(&merged).contains(&first));
+/// assert_eq!(ids(&merged), ids(&merge(&b, &a)));
+/// // Merging again changes nothing.
+/// assert_eq!(ids(&merge(&merged, &a)), ids(&merged));
+/// ```
+pub fn merge(a: &Project, b: &Project) -> Project {
+    let mut project = a.clone();
+    for (name, canvas) in &b.canvas {
+        let merged = match a.canvas.get(name) {
+            Some(mine) => merge_canvas(mine, canvas),
+            None => canvas.clone(),
+        };
+        project.canvas.insert(name.clone(), merged);
+    }
+    for name in &b.pages {
+        if !project.pages.contains(name) {
+            project.pages.push(name.clone());
+        }
+    }
+    project
+}
+
+/// Finished strokes whose transform changed.
+type Moved = (Changed<Transform>, Without<CurrentCurveMarker>);
+
+/// Stamp strokes moved since last run, so the move wins over older changes when merged.
+pub(crate) fn stamp_moves(mut curves: Query<(&mut Curve, Ref<Transform>), Moved>) {
+    for (mut curve, transform) in curves.iter_mut() {
+        if !transform.is_added() {
+            curve.stamp = Stamp::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MAIN_CANVAS;
+
+    fn stroke(x: f32) -> Curve {
+        Curve {
+            points: vec![Vec2::new(x, 0.0), Vec2::new(x, 10.0)],
+            which: 1,
+            id: Id::new(),
+            stamp: stamp(1, 0),
+            ..Default::default()
+        }
+    }
+
+    fn stamp(time: u64, replica: u64) -> Stamp {
+        Stamp { time, replica }
+    }
+
+    /// `curve` moved by `x` at `stamp`.
+    fn moved(curve: &Curve, x: f32, stamp: Stamp) -> Curve {
+        let mut curve = curve.clone();
+        curve.points.iter_mut().for_each(|p| p.x += x);
+        curve.stamp = stamp;
+        curve
+    }
+
+    /// A stroke's id, when it changed and where it is.
+    type Version = (Id, Stamp, Vec<Vec2>);
+
+    /// Strokes by id, and the removed ids.
+    fn summary(canvas: &Canvas) -> (Vec<Version>, BTreeMap<Id, Stamp>) {
+        let mut strokes: Vec<_> = canvas
+            .curves()
+            .map(|x| (x.id, x.stamp, x.points.clone()))
+            .collect();
+        strokes.sort_by_key(|x| x.0);
+        (strokes, canvas.removed.clone())
+    }
+
+    /// Both merges of `a` and `b`, checked to agree.
+    fn merged(a: &Canvas, b: &Canvas) -> Canvas {
+        let ab = merge_canvas(a, b);
+        assert_eq!(summary(&ab), summary(&merge_canvas(b, a)));
+        ab
+    }
+
+    #[test]
+    fn newer_move_wins() {
+        let base = stroke(0.0);
+        let a = Canvas::new(vec![moved(&base, 1.0, stamp(2, 1))], vec![]);
+        let b = Canvas::new(vec![moved(&base, 2.0, stamp(3, 2))], vec![]);
+        let (strokes, _) = summary(&merged(&a, &b));
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].1, stamp(3, 2));
+        assert_eq!(strokes[0].2[0].x, 2.0);
+    }
+
+    #[test]
+    fn ties_go_to_the_greater_replica() {
+        let base = stroke(0.0);
+        let a = Canvas::new(vec![moved(&base, 1.0, stamp(2, 7))], vec![]);
+        let b = Canvas::new(vec![moved(&base, 2.0, stamp(2, 3))], vec![]);
+        let (strokes, _) = summary(&merged(&a, &b));
+        assert_eq!(strokes[0].2[0].x, 1.0);
+    }
+
+    #[test]
+    fn changes_after_removal_stay() {
+        let base = stroke(0.0);
+        let a = Canvas::new(vec![moved(&base, 1.0, stamp(5, 1))], vec![]);
+        let mut b = Canvas::default();
+        b.removed.insert(base.id, stamp(4, 2));
+        let (strokes, removed) = summary(&merged(&a, &b));
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].2[0].x, 1.0);
+        assert_eq!(removed.get(&base.id), Some(&stamp(4, 2)));
+
+        // Removed after the change, it stays removed.
+        b.removed.insert(base.id, stamp(6, 2));
+        assert!(summary(&merged(&a, &b)).0.is_empty());
+    }
+
+    #[test]
+    fn removals_merge_alike_both_ways() {
+        let strokes: Vec<Curve> = (0..4).map(|x| stroke(x as f32)).collect();
+        let ids: Vec<Id> = strokes.iter().map(|x| x.id).collect();
+        let mut a = Canvas::new(strokes.clone(), vec![]);
+        let mut b = Canvas::new(strokes, vec![]);
+        assert!(a.remove(ids[0]));
+        assert!(b.remove(ids[1]));
+        // Both remove the same stroke, at other times.
+        a.removed.insert(ids[2], stamp(7, 1));
+        a.strokes.retain(|x| x.id != ids[2]);
+        b.removed.insert(ids[2], stamp(8, 2));
+        b.strokes.retain(|x| x.id != ids[2]);
+        let (strokes, removed) = summary(&merged(&a, &b));
+        assert_eq!(strokes.iter().map(|x| x.0).collect::<Vec<_>>(), [ids[3]]);
+        assert_eq!(removed.len(), 3);
+        assert_eq!(removed[&ids[2]], stamp(8, 2));
+    }
+
+    #[test]
+    fn merging_with_itself_changes_nothing() {
+        let mut canvas = Canvas::new((0..3).map(|x| stroke(x as f32)).collect(), vec![]);
+        let first = canvas.strokes[0].id;
+        canvas.remove(first);
+        assert_eq!(summary(&merged(&canvas, &canvas)), summary(&canvas));
+    }
+
+    #[test]
+    fn pages_are_united() {
+        let mut a = Project::new("Notes", Canvas::new(vec![stroke(0.0)], vec![]));
+        let mut b = a.clone();
+        for (project, page) in [(&mut a, "A"), (&mut b, "B")] {
+            project
+                .canvas
+                .insert(page.to_owned(), Canvas::new(vec![stroke(1.0)], vec![]));
+            project.pages.push(page.to_owned());
+        }
+        b.canvas
+            .get_mut(MAIN_CANVAS)
+            .unwrap()
+            .strokes
+            .push(stroke(2.0));
+
+        let ab = merge(&a, &b);
+        assert_eq!(ab.pages, [MAIN_CANVAS, "A", "B"]);
+        assert_eq!(merge(&b, &a).pages, [MAIN_CANVAS, "B", "A"]);
+        assert_eq!(ab.canvas.len(), 3);
+        assert_eq!(ab.canvas[MAIN_CANVAS].curves().count(), 2);
+        assert_eq!(ab.canvas["B"].curves().count(), 1);
+    }
+
+    #[test]
+    fn random_numbers_differ() {
+        let numbers: HashSet<u64> = (0..64).map(|_| random()).collect();
+        assert_eq!(numbers.len(), 64);
+    }
+}
@@ -0,0 +1,466 @@
+//! Drawing together with peers over the network.
+//!
+//! One instance hosts and others join it over TCP. Each side sends [`Message`]s as lines of JSON,
+//! which the host relays to the other peers, naming who they came from. Strokes being drawn are
+//! streamed as they grow and sent again once finished, so peers see them drawn live and end up
+//! with the same strokes. Remote strokes being drawn are marked with
+//! [`CurrentCurveMarker::Network`] and the peer drawing them.
+//!
+//! Joining peers are sent the strokes of the page shown on either side. Only drawing is shared:
+//! erasing, undo and changes to strokes stay local.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CurrentCurveMarker, Curve, IncomingPoints, StrokeFinished, args::Collab, finish_stroke,
+    ink::StrokePoint, merge::Id, spawn_curve, ui::OverlayEvent,
+};
+
+/// Peer id of the host.
+const HOST: usize = 0;
+/// How long writing to a peer may stall before it is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest line read from a peer, in bytes. Longer ones end the connection.
+const MAX_LINE: u64 = 16 << 20;
+
+/// What peers tell each other about strokes, named by their ids.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Message {
+    /// A stroke is started as `curve`, with its id, brush and first point.
+    Begin(Box<Curve>),
+    /// More points of a stroke being drawn.
+    Points {
+        stroke: Id,
+        points: Vec<StrokePoint>,
+    },
+    /// A stroke is finished as `curve`, or came to nothing.
+    Finish {
+        stroke: Id,
+        curve: Option<Box<Curve>>,
+    },
+    /// A finished stroke, sent to peers joining.
+    Stroke(Box<Curve>),
+}
+
+/// A message on the wire, from the peer `from`. The host fills in who it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Envelope {
+    from: Option<usize>,
+    message: Message,
+}
+
+/// What comes from the connections.
+enum Received {
+    /// A peer connected, or the host for peers joining.
+    Joined(usize),
+    Message(Envelope),
+    /// A peer disconnected, or the host for peers joining.
+    Left(usize),
+}
+
+/// Envelope to send, to one peer or to all.
+type Outgoing = (Option<usize>, Envelope);
+
+/// Connections to peers.
+#[derive(Resource)]
+pub struct Session {
+    /// Peer id this side sends as, or `None` when joining, as the host names peers.
+    peer: Option<usize>,
+    outgoing: Sender<Outgoing>,
+    received: Mutex<Receiver<Received>>,
+    /// Strokes being drawn here.
+    local: HashMap<Entity, Id>,
+    /// Strokes being drawn by peers, with who draws them.
+    remote: HashMap<(usize, Id), Entity>,
+}
+
+impl Session {
+    fn send(&self, to: Option<usize>, message: Message) {
+        // Fails only once the connections are gone.
+        let _ = self.outgoing.send((
+            to,
+            Envelope {
+                from: self.peer,
+                message,
+            },
+        ));
+    }
+}
+
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, connect.run_if(resource_exists::<Collab>))
+            .add_systems(
+                PreUpdate,
+                (share_strokes, receive_strokes)
+                    .chain()
+                    .after(finish_stroke)
+                    .run_if(resource_exists::<Session>),
+            );
+    }
+}
+
+/// Start hosting or joining as asked.
+fn connect(
+    collab: Res<Collab>,
+    mut commands: Commands,
+    mut overlay_event: EventWriter<OverlayEvent>,
+) {
+    let (received, receiver) = mpsc::channel();
+    let (outgoing, to_send) = mpsc::channel();
+    let peer = match &*collab {
+        Collab::Host(address) => match host(address, received, to_send) {
+            Ok(()) => {
+                info!("Hosting at {address}");
+                overlay_event.write(OverlayEvent::Transient(format!("Hosting at {address}")));
+                Some(HOST)
+            }
+            Err(e) => {
+                warn!("Cannot host at {address}: {e}");
+                overlay_event.write(OverlayEvent::Transient(format!("Cannot host: {e}")));
+                return;
+            }
+        },
+        Collab::Join(address) => {
+            join(address.clone(), received, to_send);
+            None
+        }
+    };
+    commands.insert_resource(Session {
+        peer,
+        outgoing,
+        received: Mutex::new(receiver),
+        local: HashMap::new(),
+        remote: HashMap::new(),
+    });
+}
+
+/// `envelope` as a line of JSON.
+fn to_line(envelope: &Envelope) -> Option<Arc<[u8]>> {
+    let mut line = serde_json::to_vec(envelope)
+        .inspect_err(|e| warn!("Cannot serialize message: {e}"))
+        .ok()?;
+    line.push(b'\n');
+    Some(line.into())
+}
+
+/// Write `lines` to `stream` until either ends, then close it so its reader sees it closed.
+/// Writes stalling for [`WRITE_TIMEOUT`] fail.
+fn write_lines(mut stream: TcpStream, lines: impl IntoIterator<Item = Arc<[u8]>>) {
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    for line in lines {
+        if stream.write_all(&line).is_err() {
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Read envelopes from `stream` until it closes or sends a line over [`MAX_LINE`] bytes, passing
+/// each to `f`.
+fn read_lines(stream: TcpStream, mut f: impl FnMut(Envelope)) {
+    let mut reader = BufReader::new(stream);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(n) if n as u64 == MAX_LINE && line.last() != Some(&b'\n') => {
+                warn!("Peer sent a message over {MAX_LINE} bytes");
+                break;
+            }
+            Ok(_) => {}
+        }
+        match serde_json::from_slice(&line) {
+            Ok(envelope) => f(envelope),
+            Err(e) => warn!("Bad message from peer: {e}"),
+        }
+    }
+}
+
+/// Lines to write to each peer of the host, by peer id. Each peer has a thread writing them, so a
+/// slow peer holds up no other.
+type Peers = Arc<Mutex<Vec<(usize, Sender<Arc<[u8]>>)>>>;
+
+/// Send `envelope` to the peers picked by `to`. Peers whose writers are gone are dropped.
+fn send(peers: &Peers, to: impl Fn(usize) -> bool, envelope: &Envelope) {
+    let Some(line) = to_line(envelope) else {
+        return;
+    };
+    peers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(peer, lines)| !to(*peer) || lines.send(line.clone()).is_ok());
+}
+
+/// Listen for peers at `address`, relaying what each sends to the others.
+fn host(address: &str, received: Sender<Received>, to_send: Receiver<Outgoing>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let peers: Peers = Arc::default();
+    let writers = peers.clone();
+    thread::spawn(move || {
+        for (to, envelope) in to_send {
+            send(&writers, |x| to.is_none_or(|to| to == x), &envelope);
+        }
+    });
+    thread::spawn(move || {
+        let connections = listener
+            .incoming()
+            .filter_map(|x| x.inspect_err(|e| warn!("Peer failed to connect: {e}")).ok());
+        for (peer, stream) in (HOST + 1..).zip(connections) {
+            let Ok(writer) = stream.try_clone() else {
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            let (lines, to_write) = mpsc::channel();
+            thread::spawn(move || write_lines(writer, to_write));
+            peers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((peer, lines));
+            let _ = received.send(Received::Joined(peer));
+            let (peers, received) = (peers.clone(), received.clone());
+            thread::spawn(move || {
+                read_lines(stream, |mut envelope| {
+                    envelope.from = Some(peer);
+                    send(&peers, |x| x != peer, &envelope);
+                    let _ = received.send(Received::Message(envelope));
+                });
+                peers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .retain(|x| x.0 != peer);
+                let _ = received.send(Received::Left(peer));
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Connect to the host at `address`. Messages to send wait until connected.
+fn join(address: String, received: Sender<Received>, to_send: Receiver<Outgoing>) {
+    thread::spawn(move || {
+        let stream = match TcpStream::connect(&address) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Cannot join {address}: {e}");
+                let _ = received.send(Received::Left(HOST));
+                return;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let Ok(writer) = stream.try_clone() else {
+            return;
+        };
+        let _ = received.send(Received::Joined(HOST));
+        thread::spawn(move || {
+            write_lines(writer, to_send.into_iter().filter_map(|x| to_line(&x.1)));
+        });
+        read_lines(stream, |envelope| {
+            let _ = received.send(Received::Message(envelope));
+        });
+        let _ = received.send(Received::Left(HOST));
+    });
+}
+
+/// Send strokes drawn here, as they start, grow and finish.
+fn share_strokes(
+    mut session: ResMut<Session>,
+    mut finished: EventReader<StrokeFinished>,
+    started: Query<(Entity, &Curve, &CurrentCurveMarker), Added<CurrentCurveMarker>>,
+    drawing: Query<(&Curve, &IncomingPoints, &CurrentCurveMarker)>,
+    curves: Query<&Curve>,
+) {
+    let local = |marker: &CurrentCurveMarker| !matches!(marker, CurrentCurveMarker::Network(_));
+    for (entity, curve, _) in started.iter().filter(|x| local(x.2)) {
+        session.local.insert(entity, curve.id());
+        session.send(None, Message::Begin(Box::new(curve.clone())));
+    }
+    for (curve, incoming, _) in drawing.iter().filter(|x| local(x.2)) {
+        if !incoming.points.is_empty() {
+            session.send(
+                None,
+                Message::Points {
+                    stroke: curve.id(),
+                    points: incoming.points.clone(),
+                },
+            );
+        }
+    }
+    for StrokeFinished(entity) in finished.read() {
+        // Strokes which came to nothing are gone by now.
+        let Some(stroke) = session.local.remove(entity) else {
+            continue;
+        };
+        session.send(
+            None,
+            Message::Finish {
+                stroke,
+                curve: curves.get(*entity).ok().cloned().map(Box::new),
+            },
+        );
+    }
+}
+
+/// Draw strokes from peers, and send strokes to peers joining.
+fn receive_strokes(
+    mut session: ResMut<Session>,
+    mut drawing: Query<&mut IncomingPoints, With<CurrentCurveMarker>>,
+    curves: Query<(&Curve, &Transform), Without<CurrentCurveMarker>>,
+    mut commands: Commands,
+    mut overlay_event: EventWriter<OverlayEvent>,
+) {
+    let received: Vec<Received> = session
+        .received
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .try_iter()
+        .collect();
+    // Strokes here, to skip those sent again.
+    let mut known: Option<HashSet<Id>> = None;
+    for received in received {
+        match received {
+            Received::Joined(peer) => {
+                let message = if session.peer == Some(HOST) {
+                    format!("Peer {peer} joined")
+                } else {
+                    "Joined host".to_owned()
+                };
+                info!("{message}");
+                overlay_event.write(OverlayEvent::Transient(message));
+                for (curve, transform) in curves.iter() {
+                    let curve = Box::new(curve.transformed(transform));
+                    session.send(Some(peer), Message::Stroke(curve));
+                }
+            }
+            Received::Left(peer) => {
+                let message = if session.peer == Some(HOST) {
+                    format!("Peer {peer} left")
+                } else {
+                    "Disconnected from host".to_owned()
+                };
+                info!("{message}");
+                overlay_event.write(OverlayEvent::Transient(message));
+                // Strokes left unfinished are dropped.
+                session.remote.retain(|(from, _), entity| {
+                    if *from == peer {
+                        commands.entity(*entity).despawn();
+                    }
+                    *from != peer
+                });
+            }
+            Received::Message(Envelope { from: None, .. }) => {
+                warn!("Message from an unnamed peer");
+            }
+            Received::Message(Envelope {
+                from: Some(from),
+                message,
+            }) => match message {
+                Message::Begin(curve) => {
+                    let stroke = curve.id();
+                    let entity = commands
+                        .spawn((
+                            *curve,
+                            CurrentCurveMarker::Network(from),
+                            IncomingPoints { points: vec![] },
+                            Transform::default(),
+                            Visibility::default(),
+                        ))
+                        .id();
+                    session.remote.insert((from, stroke), entity);
+                }
+                Message::Points { stroke, points } => {
+                    let Some(entity) = session.remote.get(&(from, stroke)) else {
+                        continue;
+                    };
+                    if let Ok(mut incoming) = drawing.get_mut(*entity) {
+                        incoming.points.extend(points);
+                    }
+                }
+                Message::Finish { stroke, curve } => {
+                    // The stroke as drawn is replaced with the finished one.
+                    if let Some(entity) = session.remote.remove(&(from, stroke)) {
+                        commands.entity(entity).despawn();
+                    }
+                    if let Some(curve) = curve.filter(|x| x.points.len() >= 2) {
+                        spawn_curve(&mut commands, *curve);
+                    }
+                }
+                Message::Stroke(curve) => {
+                    let known =
+                        known.get_or_insert_with(|| curves.iter().map(|x| x.0.id()).collect());
+                    if known.insert(curve.id()) {
+                        spawn_curve(&mut commands, *curve);
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both ends of a connection.
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (stream, listener.accept().unwrap().0)
+    }
+
+    fn envelope(stroke: Id) -> Envelope {
+        Envelope {
+            from: Some(HOST),
+            message: Message::Points {
+                stroke,
+                points: vec![StrokePoint::new(Vec2::ONE, 0.5)],
+            },
+        }
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let (a, b) = connection();
+        let ids = [Id::new(), Id::new()];
+        let lines: Vec<Arc<[u8]>> = ids.iter().filter_map(|x| to_line(&envelope(*x))).collect();
+        thread::spawn(move || write_lines(a, lines));
+        let mut read = vec![];
+        read_lines(b, |x| read.push(x));
+        assert_eq!(read.len(), 2);
+        for (envelope, id) in read.iter().zip(ids) {
+            assert_eq!(envelope.from, Some(HOST));
+            assert!(matches!(envelope.message, Message::Points { stroke, .. } if stroke == id));
+        }
+    }
+
+    #[test]
+    fn long_lines_end_the_connection() {
+        let (a, b) = connection();
+        let mut long = vec![b' '; MAX_LINE as usize + 1];
+        long.push(b'\n');
+        let lines: Vec<Arc<[u8]>> = [Some(long.into()), to_line(&envelope(Id::new()))]
+            .into_iter()
+            .flatten()
+            .collect();
+        thread::spawn(move || write_lines(a, lines));
+        let mut read = 0;
+        read_lines(b, |_| read += 1);
+        assert_eq!(read, 0);
+    }
+}
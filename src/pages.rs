@@ -0,0 +1,696 @@
+//! Pages of a notebook, each a canvas of the project.
+//!
+//! Only the active page has its strokes spawned as [`Curve`] entities. The others are kept as
+//! [`Canvas`]es in [`Notebook`], and swapped in when switching pages, along with their undo
+//! history.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    input::keyboard::{Key, KeyboardInput},
+    prelude::*,
+};
+
+use crate::{
+    CurrentCurveMarker, Curve,
+    history::{Edit, History, StoredHistory},
+    merge::Id,
+    spawn_curve,
+    storage::{Canvas, MAIN_CANVAS, Project},
+};
+
+/// Pages of the open project.
+#[derive(Resource, Debug, Clone)]
+pub struct Notebook {
+    /// Canvas of each page. That of the active page is stale while its strokes are entities.
+    canvas: HashMap<String, Canvas>,
+    /// Page names in order.
+    order: Vec<String>,
+    active: String,
+    /// Strokes of the active page when it was last shown or saved, to tell which are removed.
+    known: HashSet<Id>,
+    /// History of each page not shown.
+    history: HashMap<String, StoredHistory>,
+}
+
+impl Default for Notebook {
+    fn default() -> Self {
+        Self {
+            canvas: HashMap::from([(MAIN_CANVAS.to_owned(), Canvas::default())]),
+            order: vec![MAIN_CANVAS.to_owned()],
+            active: MAIN_CANVAS.to_owned(),
+            known: HashSet::new(),
+            history: HashMap::new(),
+        }
+    }
+}
+
+impl Notebook {
+    /// Pages of `project`, showing the first one.
+    pub fn from_project(project: Project) -> Self {
+        let order: Vec<String> = project
+            .pages()
+            .into_iter()
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        let Some(active) = order.first().cloned() else {
+            return Self::default();
+        };
+        Self {
+            canvas: project.canvas,
+            order,
+            active,
+            known: HashSet::new(),
+            history: HashMap::new(),
+        }
+    }
+
+    /// Name of the page shown.
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    /// Page names in order.
+    pub fn pages(&self) -> &[String] {
+        &self.order
+    }
+
+    /// The active page, with `strokes` as they are on screen.
+    pub fn active_canvas(&self, strokes: Vec<Curve>) -> Canvas {
+        let Some(stale) = self.canvas.get(&self.active) else {
+            return Canvas::new(strokes, vec![]);
+        };
+        let mut canvas = Canvas::new(strokes, stale.elements.clone());
+        canvas.removed = stale.removed.clone();
+        canvas
+    }
+
+    /// Mark strokes of `page` since removed from screen, where `strokes` are left.
+    pub fn store_removed(&mut self, page: &str, strokes: &[Curve]) {
+        let now: HashSet<Id> = strokes.iter().map(Curve::id).collect();
+        if let Some(canvas) = self.canvas.get_mut(page) {
+            canvas.update_removed(&self.known, &now);
+        }
+        self.known = now;
+    }
+
+    /// All pages as a project, with `strokes` of the active page as they are on screen.
+    pub fn project(&self, title: impl Into<String>, strokes: Vec<Curve>) -> Project {
+        let mut project = Project::new(title, Canvas::default());
+        project.canvas = self.canvas.clone();
+        project
+            .canvas
+            .insert(self.active.clone(), self.active_canvas(strokes));
+        project.pages = self.order.clone();
+        project
+    }
+
+    /// Strokes of the active page, shapes included, to spawn as entities.
+    pub fn take_active_strokes(&mut self) -> Vec<Curve> {
+        let curves = self
+            .canvas
+            .get_mut(&self.active)
+            .map(Canvas::take_curves)
+            .unwrap_or_default();
+        self.known = curves.iter().map(Curve::id).collect();
+        curves
+    }
+
+    /// A name for a new page, numbered after the pages so far.
+    fn new_name(&self) -> String {
+        (self.order.len() + 1..)
+            .map(|n| format!("Page {n}"))
+            .find(|x| !self.canvas.contains_key(x))
+            .unwrap_or_default()
+    }
+
+    fn step(&self, offset: isize) -> Option<&String> {
+        let i = self.order.iter().position(|x| *x == self.active)?;
+        let n = self.order.len() as isize;
+        self.order.get((i as isize + offset).rem_euclid(n) as usize)
+    }
+}
+
+/// Name of a page to show.
+pub fn page_title(name: &str) -> &str {
+    if name == MAIN_CANVAS { "Main" } else { name }
+}
+
+/// Whether `name` may name a page: not empty, and not reserved.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.')
+}
+
+#[derive(Event, Debug, Clone)]
+pub enum PageEvent {
+    /// Add an empty page after the active one and switch to it.
+    New,
+    Switch(String),
+    Next,
+    Previous,
+    /// The main page keeps its reserved name.
+    Rename {
+        from: String,
+        to: String,
+    },
+    /// Move the active page `n` places later, or earlier when negative.
+    Move(isize),
+    /// The main page cannot be deleted. Deleting is recorded in [`History`].
+    Delete(String),
+    /// Delete a page again, as when redoing its deletion. Edits left to redo are kept.
+    Redelete(String),
+    /// Bring back a deleted page at place `index` and switch to it, as when undoing the deletion.
+    Restore {
+        name: String,
+        index: usize,
+        canvas: Canvas,
+        history: StoredHistory,
+    },
+}
+
+/// New name of the active page being typed, if any. Keyboard commands are off meanwhile.
+#[derive(Resource, Debug, Default)]
+pub struct Renaming(pub Option<String>);
+
+pub fn not_renaming(renaming: Res<Renaming>) -> bool {
+    renaming.0.is_none()
+}
+
+/// A button of the page strip.
+#[derive(Component, Debug, Clone)]
+pub enum PageButton {
+    Page(String),
+    New,
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+struct PageStrip;
+
+pub struct PagesPlugin;
+
+impl Plugin for PagesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PageEvent>()
+            .init_resource::<Notebook>()
+            .init_resource::<Renaming>()
+            .add_systems(Startup, spawn_page_strip)
+            .add_systems(
+                Update,
+                (
+                    handle_page_strip,
+                    type_page_name,
+                    handle_pages,
+                    update_page_strip,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Apply page events, then swap the strokes on screen for those of the new active page.
+fn handle_pages(
+    mut events: EventReader<PageEvent>,
+    mut notebook: ResMut<Notebook>,
+    mut commands: Commands,
+    curves: Query<(Entity, &Curve, &Transform, Has<CurrentCurveMarker>)>,
+    mut history: ResMut<History>,
+) {
+    // The page on screen, followed through renames. `None` once deleted.
+    let mut shown = Some(notebook.active.clone());
+    // Deletions to record, and which one is of the page on screen, still missing its strokes.
+    let mut deleted = vec![];
+    let mut left = None;
+    let mut restored = vec![];
+    for event in events.read() {
+        let notebook = &mut *notebook;
+        match event {
+            PageEvent::New => {
+                let name = notebook.new_name();
+                let i = notebook
+                    .order
+                    .iter()
+                    .position(|x| *x == notebook.active)
+                    .map_or(notebook.order.len(), |x| x + 1);
+                notebook.canvas.insert(name.clone(), Canvas::default());
+                notebook.order.insert(i, name.clone());
+                notebook.active = name;
+            }
+            PageEvent::Switch(name) => {
+                if notebook.canvas.contains_key(name) {
+                    notebook.active = name.clone();
+                }
+            }
+            PageEvent::Next | PageEvent::Previous => {
+                let offset = if matches!(event, PageEvent::Next) {
+                    1
+                } else {
+                    -1
+                };
+                if let Some(name) = notebook.step(offset) {
+                    notebook.active = name.clone();
+                }
+            }
+            PageEvent::Rename { from, to } => {
+                let to = to.trim();
+                if from == MAIN_CANVAS || !valid_name(to) || notebook.canvas.contains_key(to) {
+                    warn!("Cannot rename page {from:?} to {to:?}");
+                    continue;
+                }
+                let Some(canvas) = notebook.canvas.remove(from) else {
+                    continue;
+                };
+                notebook.canvas.insert(to.to_owned(), canvas);
+                if let Some(history) = notebook.history.remove(from) {
+                    notebook.history.insert(to.to_owned(), history);
+                }
+                for name in notebook
+                    .order
+                    .iter_mut()
+                    .chain([&mut notebook.active])
+                    .chain(shown.as_mut())
+                {
+                    if name == from {
+                        *name = to.to_owned();
+                    }
+                }
+            }
+            PageEvent::Move(offset) => {
+                let Some(i) = notebook.order.iter().position(|x| *x == notebook.active) else {
+                    continue;
+                };
+                let j = (i as isize + offset).clamp(0, notebook.order.len() as isize - 1);
+                let name = notebook.order.remove(i);
+                notebook.order.insert(j as usize, name);
+            }
+            PageEvent::Delete(name) | PageEvent::Redelete(name) => {
+                let canvas = (name != MAIN_CANVAS)
+                    .then(|| notebook.canvas.remove(name))
+                    .flatten();
+                let Some(canvas) = canvas else {
+                    warn!("Cannot delete page {name:?}");
+                    continue;
+                };
+                let i = notebook.order.iter().position(|x| x == name);
+                notebook.order.retain(|x| x != name);
+                if notebook.active == *name {
+                    let i = i.unwrap_or(0).min(notebook.order.len().saturating_sub(1));
+                    notebook.active = notebook.order[i].clone();
+                }
+                if shown.as_ref() == Some(name) {
+                    shown = None;
+                    left = Some(deleted.len());
+                }
+                let edit = Edit::DeletePage {
+                    name: name.clone(),
+                    index: i.unwrap_or(notebook.order.len()),
+                    canvas,
+                    history: notebook.history.remove(name).unwrap_or_default(),
+                };
+                deleted.push((edit, matches!(event, PageEvent::Redelete(_))));
+            }
+            PageEvent::Restore {
+                name,
+                index,
+                canvas,
+                history,
+            } => {
+                if notebook.canvas.contains_key(name) {
+                    warn!("Cannot restore page {name:?} over another of that name");
+                    continue;
+                }
+                notebook.canvas.insert(name.clone(), canvas.clone());
+                notebook.history.insert(name.clone(), history.clone());
+                let index = (*index).min(notebook.order.len());
+                notebook.order.insert(index, name.clone());
+                notebook.active = name.clone();
+                // Redoing deletes the page again, taking its canvas as it is then.
+                restored.push(Edit::DeletePage {
+                    name: name.clone(),
+                    index,
+                    canvas: Canvas::default(),
+                    history: StoredHistory::default(),
+                });
+            }
+        }
+    }
+    if shown.as_ref() != Some(&notebook.active) {
+        // Strokes being drawn are dropped.
+        let mut strokes = vec![];
+        let mut entities = vec![];
+        for (entity, curve, transform, current) in curves.iter() {
+            if !current {
+                strokes.push(curve.transformed(transform));
+                entities.push((curve.id(), entity, *transform));
+            }
+            commands.entity(entity).despawn();
+        }
+        // Edits refer to strokes of the page left, so its history goes along with them.
+        let stored = history.store(entities);
+        let canvas = match shown {
+            Some(shown) => {
+                notebook.store_removed(&shown, &strokes);
+                notebook.history.insert(shown.clone(), stored);
+                notebook.canvas.get_mut(&shown)
+            }
+            // Its deletion keeps the strokes and history, for undo.
+            None => match left.and_then(|i| deleted.get_mut(i)) {
+                Some((
+                    Edit::DeletePage {
+                        canvas, history, ..
+                    },
+                    _,
+                )) => {
+                    *history = stored;
+                    Some(canvas)
+                }
+                _ => None,
+            },
+        };
+        if let Some(canvas) = canvas {
+            let removed = std::mem::take(&mut canvas.removed);
+            *canvas = Canvas::new(strokes, std::mem::take(&mut canvas.elements));
+            canvas.removed = removed;
+        }
+        let spawned: Vec<(Id, Entity)> = notebook
+            .take_active_strokes()
+            .into_iter()
+            .map(|curve| (curve.id(), spawn_curve(&mut commands, curve)))
+            .collect();
+        let active = notebook.active.clone();
+        let stored = notebook.history.remove(&active).unwrap_or_default();
+        *history = History::restore(stored, spawned);
+        info!("Switched to page {:?}", page_title(&notebook.active));
+    }
+    for (edit, redone) in deleted {
+        if redone {
+            history.record_redone(edit);
+        } else {
+            history.record(edit);
+        }
+    }
+    for edit in restored {
+        history.record_undone(edit);
+    }
+}
+
+fn spawn_page_strip(mut commands: Commands) {
+    commands.spawn((
+        PageStrip,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            width: Val::Percent(100.),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::Center,
+            column_gap: Val::Px(8.0),
+            ..default()
+        },
+    ));
+}
+
+/// Switch to a page by clicking it, or rename the active one by clicking it again.
+fn handle_page_strip(
+    buttons: Query<(&Interaction, &PageButton), Changed<Interaction>>,
+    notebook: Res<Notebook>,
+    mut renaming: ResMut<Renaming>,
+    mut events: EventWriter<PageEvent>,
+) {
+    for (interaction, button) in buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            PageButton::Page(name) if *name == notebook.active => {
+                if name != MAIN_CANVAS {
+                    renaming.0 = Some(name.clone());
+                }
+            }
+            PageButton::Page(name) => {
+                renaming.0 = None;
+                events.write(PageEvent::Switch(name.clone()));
+            }
+            PageButton::New => {
+                renaming.0 = None;
+                events.write(PageEvent::New);
+            }
+        }
+    }
+}
+
+/// Type the new name of the active page. Enter renames it, Escape gives up.
+fn type_page_name(
+    mut keys: EventReader<KeyboardInput>,
+    mut renaming: ResMut<Renaming>,
+    notebook: Res<Notebook>,
+    mut events: EventWriter<PageEvent>,
+) {
+    if renaming.0.is_none() {
+        keys.clear();
+        return;
+    }
+    for key in keys.read() {
+        if !key.state.is_pressed() {
+            continue;
+        }
+        let Some(name) = renaming.0.as_mut() else {
+            break;
+        };
+        match &key.logical_key {
+            Key::Character(text) => name.extend(text.chars().filter(|x| !x.is_control())),
+            Key::Space => name.push(' '),
+            Key::Backspace => {
+                name.pop();
+            }
+            Key::Enter => {
+                if let Some(to) = renaming.0.take()
+                    && to != notebook.active
+                {
+                    events.write(PageEvent::Rename {
+                        from: notebook.active.clone(),
+                        to,
+                    });
+                }
+            }
+            Key::Escape => renaming.0 = None,
+            _ => {}
+        }
+    }
+}
+
+/// Show a button for each page, the active one outlined, and one to add a page.
+fn update_page_strip(
+    mut commands: Commands,
+    notebook: Res<Notebook>,
+    renaming: Res<Renaming>,
+    strip: Single<Entity, With<PageStrip>>,
+) {
+    if !notebook.is_changed() && !renaming.is_changed() {
+        return;
+    }
+    let button = Node {
+        padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+        border: UiRect::all(Val::Px(2.0)),
+        ..default()
+    };
+    commands
+        .entity(*strip)
+        .despawn_related::<Children>()
+        .with_children(|parent| {
+            for name in &notebook.order {
+                let active = *name == notebook.active;
+                let label = match &renaming.0 {
+                    Some(typed) if active => format!("{typed}_"),
+                    _ => page_title(name).to_owned(),
+                };
+                let border = if active {
+                    Color::srgb(0.8, 0.8, 0.8)
+                } else {
+                    Color::NONE
+                };
+                parent
+                    .spawn((
+                        Button,
+                        PageButton::Page(name.clone()),
+                        button.clone(),
+                        BorderColor(border),
+                        BorderRadius::all(Val::Px(4.0)),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((Text::new(label),));
+                    });
+            }
+            parent
+                .spawn((
+                    Button,
+                    PageButton::New,
+                    button.clone(),
+                    BorderColor(Color::NONE),
+                    BorderRadius::all(Val::Px(4.0)),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((Text::new("+"),));
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{HistoryEvent, HistoryPlugin, RecordEdit, StrokeRecord};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, HistoryPlugin, PagesPlugin))
+            .add_event::<KeyboardInput>();
+        app.update();
+        app
+    }
+
+    fn strokes(app: &mut App) -> usize {
+        app.world_mut().query::<&Curve>().iter(app.world()).count()
+    }
+
+    /// Draw a stroke on the page shown, recording it.
+    fn draw(app: &mut App) -> Entity {
+        let curve: Curve =
+            serde_json::from_str(r#"{"points": [[0, 0], [10, 10]], "which": 1}"#).unwrap();
+        let curve = Curve {
+            id: Id::new(),
+            ..curve
+        };
+        let entity = spawn_curve(&mut app.world_mut().commands(), curve.clone());
+        run(
+            app,
+            RecordEdit(Edit::add(StrokeRecord {
+                entity,
+                curve,
+                transform: Transform::default(),
+            })),
+        );
+        entity
+    }
+
+    fn run(app: &mut App, event: impl Event) {
+        app.world_mut().send_event(event);
+        // Undo and redo take a frame to reach the pages.
+        app.update();
+        app.update();
+    }
+
+    #[test]
+    fn deleting_a_page_is_undone() {
+        let mut app = app();
+        run(&mut app, PageEvent::New);
+        let stroke = serde_json::from_str(r#"{"points": [[0, 0], [10, 10]], "which": 1}"#).unwrap();
+        spawn_curve(&mut app.world_mut().commands(), stroke);
+        app.update();
+        run(&mut app, PageEvent::Delete("Page 2".to_owned()));
+        let notebook = app.world().resource::<Notebook>();
+        assert_eq!(notebook.pages(), [MAIN_CANVAS]);
+        assert_eq!(strokes(&mut app), 0);
+
+        run(&mut app, HistoryEvent::Undo);
+        let notebook = app.world().resource::<Notebook>();
+        assert_eq!(notebook.pages(), [MAIN_CANVAS, "Page 2"]);
+        assert_eq!(notebook.active(), "Page 2");
+        assert_eq!(strokes(&mut app), 1);
+
+        run(&mut app, HistoryEvent::Redo);
+        assert_eq!(app.world().resource::<Notebook>().pages(), [MAIN_CANVAS]);
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(strokes(&mut app), 1);
+    }
+
+    #[test]
+    fn deleting_another_page_stays_put() {
+        let mut app = app();
+        run(&mut app, PageEvent::New);
+        run(&mut app, PageEvent::Switch(MAIN_CANVAS.to_owned()));
+        let stroke = serde_json::from_str(r#"{"points": [[0, 0], [10, 10]], "which": 1}"#).unwrap();
+        spawn_curve(&mut app.world_mut().commands(), stroke);
+        app.update();
+        run(&mut app, PageEvent::Delete("Page 2".to_owned()));
+        assert_eq!(app.world().resource::<Notebook>().active(), MAIN_CANVAS);
+        assert_eq!(strokes(&mut app), 1);
+
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(app.world().resource::<Notebook>().active(), "Page 2");
+        run(&mut app, PageEvent::Switch(MAIN_CANVAS.to_owned()));
+        assert_eq!(strokes(&mut app), 1);
+    }
+
+    #[test]
+    fn redoing_a_deletion_keeps_what_is_left_to_redo() {
+        let mut app = app();
+        run(&mut app, PageEvent::New);
+        run(&mut app, PageEvent::Delete("Page 2".to_owned()));
+        run(&mut app, HistoryEvent::Undo);
+        run(&mut app, PageEvent::Switch(MAIN_CANVAS.to_owned()));
+        draw(&mut app);
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(strokes(&mut app), 0);
+
+        // Page 2 keeps the deletion to redo, and the main page the stroke.
+        run(&mut app, PageEvent::Switch("Page 2".to_owned()));
+        run(&mut app, HistoryEvent::Redo);
+        let notebook = app.world().resource::<Notebook>();
+        assert_eq!(notebook.pages(), [MAIN_CANVAS]);
+        run(&mut app, HistoryEvent::Redo);
+        assert_eq!(strokes(&mut app), 1);
+        run(&mut app, HistoryEvent::Undo);
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(app.world().resource::<Notebook>().active(), "Page 2");
+    }
+
+    #[test]
+    fn pages_keep_their_history() {
+        let mut app = app();
+        draw(&mut app);
+        run(&mut app, PageEvent::New);
+        draw(&mut app);
+        draw(&mut app);
+        run(&mut app, PageEvent::Switch(MAIN_CANVAS.to_owned()));
+        assert_eq!(strokes(&mut app), 1);
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(strokes(&mut app), 0);
+        run(&mut app, HistoryEvent::Redo);
+        assert_eq!(strokes(&mut app), 1);
+
+        run(&mut app, PageEvent::Switch("Page 2".to_owned()));
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(strokes(&mut app), 1);
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(strokes(&mut app), 0);
+    }
+
+    #[test]
+    fn moves_are_undone_on_pages_shown_again() {
+        let mut app = app();
+        let entity = draw(&mut app);
+        let moved = Transform::from_xyz(5.0, 0.0, 0.0).with_scale(Vec3::splat(2.0));
+        app.world_mut().entity_mut(entity).insert(moved);
+        run(
+            &mut app,
+            RecordEdit(Edit::Transform(vec![(entity, Transform::default(), moved)])),
+        );
+        run(&mut app, PageEvent::New);
+        run(&mut app, PageEvent::Switch(MAIN_CANVAS.to_owned()));
+        run(&mut app, HistoryEvent::Undo);
+        let (curve, transform) = app
+            .world_mut()
+            .query::<(&Curve, &Transform)>()
+            .single(app.world())
+            .unwrap();
+        let points = curve.transformed(transform).points;
+        assert!(points[0].distance(Vec2::ZERO) < 1e-4);
+        assert!(points[1].distance(Vec2::splat(10.0)) < 1e-4);
+    }
+
+    #[test]
+    fn main_page_stays() {
+        let mut app = app();
+        run(&mut app, PageEvent::Delete(MAIN_CANVAS.to_owned()));
+        run(&mut app, HistoryEvent::Undo);
+        assert_eq!(app.world().resource::<Notebook>().pages(), [MAIN_CANVAS]);
+    }
+}
@@ -0,0 +1,432 @@
+//! Replay of how a page was drawn.
+//!
+//! Strokes are redrawn in the order they were drawn, point by point at the times kept with them,
+//! at real speed or faster. Breaks between strokes are cut to [`MAX_PAUSE`], and strokes kept
+//! without times are drawn at an even pace. A scrubber shows how far along the replay is, and
+//! jumps to where it is clicked or dragged.
+//!
+//! The replay draws copies of the strokes on a render layer of their own, which the camera shows
+//! instead of the page, so the page is left as it was. Editing waits until the replay is stopped.
+
+use std::time::Duration;
+
+use bevy::{prelude::*, render::view::RenderLayers, ui::RelativeCursorPosition};
+
+use crate::{CurrentCurveMarker, Curve, camera::MeshDetail, set_curve_mesh, ui::OverlayEvent};
+
+/// Render layer of replayed strokes.
+const REPLAY_LAYER: usize = 1;
+/// Longest break between strokes. Longer ones are cut short.
+const MAX_PAUSE: Duration = Duration::from_secs(1);
+/// Break before strokes drawn at an unknown time.
+const UNTIMED_PAUSE: Duration = Duration::from_millis(200);
+/// Time taken by strokes kept without times.
+const UNTIMED_STROKE: Duration = Duration::from_millis(400);
+/// Time skipped by the arrow keys.
+const SEEK_STEP: Duration = Duration::from_secs(5);
+/// Fastest replay, in times real speed.
+const MAX_SPEED: f32 = 64.0;
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum PlaybackEvent {
+    /// Replay the strokes shown, from the first.
+    Start,
+    /// Stop replaying and show the page again.
+    Stop,
+}
+
+/// A replay going on.
+#[derive(Resource, Debug)]
+pub struct Playback {
+    /// Time into the replay.
+    position: Duration,
+    /// Time the whole replay takes at real speed.
+    length: Duration,
+    /// How many times faster than drawn.
+    speed: f32,
+    paused: bool,
+}
+
+/// Copy of a stroke being replayed, with how many of its points are shown.
+#[derive(Component, Debug)]
+struct Replayed {
+    curve: Curve,
+    shown: usize,
+}
+
+/// When each point of a replayed stroke is drawn, as time into the replay.
+#[derive(Clone, Component, Default, Reflect)]
+#[reflect(Component)]
+struct ReplaySchedule {
+    points: Vec<Duration>,
+}
+
+/// Entities a replay spawns, to despawn when it stops.
+type ReplayParts = Or<(With<Replayed>, With<PlaybackBar>)>;
+
+/// Bar with the scrubber and the state of the replay.
+#[derive(Component, Debug)]
+struct PlaybackBar;
+
+#[derive(Component, Debug)]
+struct Scrubber;
+
+/// Part of the scrubber replayed so far.
+#[derive(Component, Debug)]
+struct ScrubberFill;
+
+#[derive(Component, Debug)]
+struct PlaybackText;
+
+/// Whether no replay is going on, so the page may be edited.
+pub fn not_replaying(playback: Option<Res<Playback>>) -> bool {
+    playback.is_none()
+}
+
+pub struct PlaybackPlugin;
+
+impl Plugin for PlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaybackEvent>()
+            .register_type::<ReplaySchedule>()
+            .add_systems(
+                Update,
+                (
+                    // Keys go first, so the key starting a replay does not stop it.
+                    playback_keys.run_if(resource_exists::<Playback>),
+                    handle_playback,
+                    (scrub, advance_playback, draw_replay, update_playback_bar)
+                        .chain()
+                        .run_if(resource_exists::<Playback>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Time from the start of `curve` to each of its points, evenly spread if not kept.
+fn drawing_times(curve: &Curve) -> Vec<Duration> {
+    let n = curve.points.len();
+    if curve.times.len() == n {
+        return curve
+            .times
+            .iter()
+            .map(|x| Duration::from_millis(u64::from(*x)))
+            .collect();
+    }
+    (0..n)
+        .map(|i| UNTIMED_STROKE * i as u32 / n.saturating_sub(1).max(1) as u32)
+        .collect()
+}
+
+/// When each point of `curves` is drawn in the replay, in the order of `curves`.
+///
+/// Strokes follow each other as they were drawn, the oldest first, with breaks cut short. Strokes
+/// with no creation time come first, in the order of their ids.
+fn timeline(curves: &[Curve]) -> Vec<Vec<Duration>> {
+    let mut order: Vec<usize> = (0..curves.len()).collect();
+    order.sort_by_key(|i| (curves[*i].created, curves[*i].id));
+    let mut timeline = vec![vec![]; curves.len()];
+    // End of the strokes so far, in milliseconds since 1970 if known, and in the replay.
+    let mut drawn_end = None;
+    let mut end = Duration::ZERO;
+    for (n, i) in order.into_iter().enumerate() {
+        let curve = &curves[i];
+        let times = drawing_times(curve);
+        let length = times.last().copied().unwrap_or_default();
+        let start = match (curve.created, drawn_end) {
+            // Strokes drawn at once, as with several fingers, are replayed at once.
+            (Some(created), Some(drawn)) if created < drawn => {
+                end.saturating_sub(Duration::from_millis(drawn - created))
+            }
+            (Some(created), Some(drawn)) => {
+                end + Duration::from_millis(created - drawn).min(MAX_PAUSE)
+            }
+            _ if n == 0 => Duration::ZERO,
+            _ => end + UNTIMED_PAUSE,
+        };
+        if start + length >= end {
+            end = start + length;
+            drawn_end = curve.created.map(|x| x + length.as_millis() as u64);
+        }
+        timeline[i] = times.into_iter().map(|x| start + x).collect();
+    }
+    timeline
+}
+
+/// Start and stop replays.
+fn handle_playback(
+    mut events: EventReader<PlaybackEvent>,
+    playback: Option<Res<Playback>>,
+    curves: Query<(&Curve, &Transform), Without<CurrentCurveMarker>>,
+    replayed: Query<Entity, ReplayParts>,
+    camera: Single<Entity, With<Camera>>,
+    mut commands: Commands,
+    mut overlay_event: EventWriter<OverlayEvent>,
+) {
+    for event in events.read() {
+        match event {
+            PlaybackEvent::Start => {
+                if playback.is_some() {
+                    continue;
+                }
+                // Strokes are replayed as they look now.
+                let curves: Vec<Curve> = curves
+                    .iter()
+                    .map(|(curve, transform)| curve.transformed(transform))
+                    .collect();
+                if curves.is_empty() {
+                    overlay_event.write(OverlayEvent::Transient("Nothing to replay".to_owned()));
+                    continue;
+                }
+                let timeline = timeline(&curves);
+                let length = timeline
+                    .iter()
+                    .filter_map(|x| x.last())
+                    .max()
+                    .copied()
+                    .unwrap_or_default();
+                for (curve, points) in curves.into_iter().zip(timeline) {
+                    commands.spawn((
+                        Replayed { curve, shown: 0 },
+                        ReplaySchedule { points },
+                        Transform::default(),
+                        Visibility::Hidden,
+                        RenderLayers::layer(REPLAY_LAYER),
+                    ));
+                }
+                commands
+                    .entity(*camera)
+                    .insert(RenderLayers::layer(REPLAY_LAYER));
+                spawn_playback_bar(&mut commands);
+                commands.insert_resource(Playback {
+                    position: Duration::ZERO,
+                    length,
+                    speed: 1.0,
+                    paused: false,
+                });
+            }
+            PlaybackEvent::Stop => {
+                if playback.is_none() {
+                    continue;
+                }
+                for entity in replayed.iter() {
+                    commands.entity(entity).despawn();
+                }
+                commands.entity(*camera).remove::<RenderLayers>();
+                commands.remove_resource::<Playback>();
+            }
+        }
+    }
+}
+
+fn spawn_playback_bar(commands: &mut Commands) {
+    commands
+        .spawn((
+            PlaybackBar,
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(72.),
+                width: Val::Percent(100.),
+                flex_direction: FlexDirection::Row,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(12.0),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Button,
+                    Scrubber,
+                    RelativeCursorPosition::default(),
+                    Node {
+                        width: Val::Percent(50.),
+                        height: Val::Px(12.),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0.3, 0.3, 0.3)),
+                    BorderRadius::MAX,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        ScrubberFill,
+                        Node {
+                            width: Val::Percent(0.),
+                            height: Val::Percent(100.),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.8, 0.8, 0.8)),
+                        BorderRadius::MAX,
+                    ));
+                });
+            parent.spawn((
+                PlaybackText,
+                Text::default(),
+                TextFont {
+                    font_size: 14.0,
+                    ..default()
+                },
+            ));
+        });
+}
+
+/// Space => pause or resume, Left/Right => skip back/ahead, Up/Down => faster/slower,
+/// Home => back to the start, T or Escape => stop.
+fn playback_keys(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<Playback>,
+    mut events: EventWriter<PlaybackEvent>,
+) {
+    if keyboard.just_pressed(KeyCode::Space) {
+        // Resuming at the end starts over.
+        if playback.paused && playback.position >= playback.length {
+            playback.position = Duration::ZERO;
+        }
+        playback.paused = !playback.paused;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        playback.position = playback.position.saturating_sub(SEEK_STEP);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        playback.position = (playback.position + SEEK_STEP).min(playback.length);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.0).min(MAX_SPEED);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.0).max(1.0);
+    }
+    if keyboard.just_pressed(KeyCode::Home) {
+        playback.position = Duration::ZERO;
+    }
+    if keyboard.any_just_pressed([KeyCode::KeyT, KeyCode::Escape]) {
+        events.write(PlaybackEvent::Stop);
+    }
+}
+
+/// Jump to where the scrubber is pressed.
+fn scrub(
+    scrubber: Query<(&Interaction, &RelativeCursorPosition), With<Scrubber>>,
+    mut playback: ResMut<Playback>,
+) {
+    for (interaction, cursor) in scrubber.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(at) = cursor.normalized {
+            playback.position = playback.length.mul_f32(at.x.clamp(0.0, 1.0));
+        }
+    }
+}
+
+fn advance_playback(time: Res<Time<Real>>, mut playback: ResMut<Playback>) {
+    if playback.paused {
+        return;
+    }
+    playback.position =
+        (playback.position + time.delta().mul_f32(playback.speed)).min(playback.length);
+    // The replay rests at the end.
+    if playback.position >= playback.length {
+        playback.paused = true;
+    }
+}
+
+/// Mesh replayed strokes as far as they are drawn at the current point of the replay.
+fn draw_replay(
+    playback: Res<Playback>,
+    mut replayed: Query<(
+        Entity,
+        &mut Replayed,
+        &ReplaySchedule,
+        Option<&Mesh2d>,
+        &mut Visibility,
+    )>,
+    detail: Res<MeshDetail>,
+    mut commands: Commands,
+    mut meshs: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (entity, mut replayed, timing, mesh2d, mut visibility) in replayed.iter_mut() {
+        let shown = timing.points.partition_point(|x| *x <= playback.position);
+        if shown == replayed.shown && !detail.is_changed() {
+            continue;
+        }
+        replayed.shown = shown;
+        if shown < 2 {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        *visibility = Visibility::Inherited;
+        let curve = &replayed.curve;
+        let part = if shown == curve.points.len() {
+            curve.clone()
+        } else {
+            curve.slice(0..shown)
+        };
+        set_curve_mesh(
+            &mut commands,
+            entity,
+            &part,
+            mesh2d,
+            &mut meshs,
+            &mut materials,
+            detail.0,
+        );
+    }
+}
+
+/// Time as minutes and seconds.
+fn clock(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn update_playback_bar(
+    playback: Res<Playback>,
+    mut fill: Single<&mut Node, With<ScrubberFill>>,
+    mut text: Single<&mut Text, With<PlaybackText>>,
+) {
+    if !playback.is_changed() {
+        return;
+    }
+    let done = if playback.length.is_zero() {
+        1.0
+    } else {
+        playback.position.as_secs_f32() / playback.length.as_secs_f32()
+    };
+    fill.width = Val::Percent(done * 100.0);
+    text.0 = format!(
+        "{} / {}  {}x{}",
+        clock(playback.position),
+        clock(playback.length),
+        playback.speed,
+        if playback.paused { "  paused" } else { "" },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+    use crate::ui::{OverlayPlugin, TransientMarker};
+
+    #[test]
+    fn empty_pages_say_so() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, OverlayPlugin, PlaybackPlugin));
+        app.world_mut().spawn(Camera::default());
+        app.world_mut().send_event(PlaybackEvent::Start);
+        app.update();
+        app.update();
+        assert!(!app.world().contains_resource::<Playback>());
+        let shown = app
+            .world_mut()
+            .query_filtered::<(), With<TransientMarker>>()
+            .iter(app.world())
+            .count();
+        assert_eq!(shown, 1);
+    }
+}
@@ -0,0 +1,433 @@
+//! Selection tool, picking strokes to move, scale and rotate.
+//!
+//! Dragging outlines strokes to select with a lasso, or with a rectangle while Shift is held, and
+//! tapping picks the stroke under the pointer. Strokes are selected when all their points are
+//! inside. Selected strokes get a box with handles: dragging inside the box moves them, the
+//! corners scale them about the opposite corner and the knob above rotates them about the center.
+//!
+//! Changes go into the [`Transform`] of each stroke, and are baked into the points when strokes
+//! are saved. Scaling keeps proportions, so any mix of changes stays a transform without shear,
+//! and shapes stay the same kind of shape.
+
+use bevy::prelude::*;
+
+use crate::{
+    CurrentCurveMarker, Curve, Tool,
+    history::{Edit, HistoryEvent, RecordEdit, StrokeRecord},
+    input::ToolPointer,
+    playback::not_replaying,
+    stroke,
+};
+
+/// Size of handles, in logical pixels on screen.
+const HANDLE_SIZE: f32 = 10.0;
+/// Distance of the rotation knob above the box, in logical pixels on screen.
+const KNOB_OFFSET: f32 = 28.0;
+/// Drags shorter than this are taps, in logical pixels on screen.
+const TAP_DISTANCE: f32 = 4.0;
+/// Smallest scale one drag shrinks a selection to.
+const MIN_SCALE: f32 = 0.05;
+const COLOR: Color = Color::srgb(0.3, 0.6, 1.0);
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum SelectionEvent {
+    /// Select nothing.
+    Clear,
+    /// Remove the selected strokes.
+    Delete,
+}
+
+/// Selected strokes and the box around them.
+#[derive(Resource, Debug, Default)]
+pub struct Selection {
+    pub entities: Vec<Entity>,
+    /// Box around the strokes as they were when selected, in canvas units.
+    bounds: Rect,
+    /// Changes to the strokes since, which the box follows.
+    frame: Transform,
+    drag: Option<SelectDrag>,
+    /// Strokes may have been changed by others, so the box needs fitting again.
+    stale: bool,
+}
+
+#[derive(Debug, Clone)]
+enum SelectDrag {
+    /// Outlining strokes to select, in canvas units.
+    Outline { points: Vec<Vec2>, rectangle: bool },
+    /// Changing the selection with `handle` from `start`, with the transforms of the box and the
+    /// strokes at the start.
+    Change {
+        handle: Handle,
+        start: Vec2,
+        frame: Transform,
+        strokes: Vec<(Entity, Transform)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Handle {
+    Move,
+    /// Scale about the corner opposite to this one, given as signs in the box.
+    Scale(Vec2),
+    Rotate,
+}
+
+/// Corners of a box in order around it, as signs from its center.
+const CORNERS: [Vec2; 4] = [
+    Vec2::new(-1.0, -1.0),
+    Vec2::new(1.0, -1.0),
+    Vec2::new(1.0, 1.0),
+    Vec2::new(-1.0, 1.0),
+];
+
+impl Selection {
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Point of the box at `signs` from its center, in canvas units.
+    fn at(&self, signs: Vec2) -> Vec2 {
+        let local = self.bounds.center() + self.bounds.half_size() * signs;
+        self.frame.transform_point(local.extend(0.0)).truncate()
+    }
+
+    /// Where the rotation knob is, `scale` canvas units a pixel.
+    fn knob(&self, scale: f32) -> Vec2 {
+        let up = (self.frame.rotation * Vec3::Y).truncate();
+        self.at(Vec2::Y) + up * KNOB_OFFSET * scale
+    }
+
+    /// Handle at `p`, with `scale` canvas units a pixel.
+    fn handle_at(&self, p: Vec2, scale: f32) -> Option<Handle> {
+        if self.entities.is_empty() {
+            return None;
+        }
+        let reach = HANDLE_SIZE * scale;
+        if p.distance(self.knob(scale)) <= reach {
+            return Some(Handle::Rotate);
+        }
+        if let Some(signs) = CORNERS.iter().find(|x| p.distance(self.at(**x)) <= reach) {
+            return Some(Handle::Scale(*signs));
+        }
+        let local = self
+            .frame
+            .compute_affine()
+            .inverse()
+            .transform_point3(p.extend(0.0))
+            .truncate();
+        self.bounds.contains(local).then_some(Handle::Move)
+    }
+
+    /// Select `entities`, which are boxed once they are spawned.
+    pub fn pick(&mut self, entities: Vec<Entity>) {
+        self.clear();
+        self.entities = entities;
+        self.stale = true;
+    }
+
+    /// Select `entities`, boxing them as they are now.
+    fn select(
+        &mut self,
+        entities: Vec<Entity>,
+        curves: &Query<(Entity, &Curve, &mut Transform), Without<CurrentCurveMarker>>,
+    ) {
+        self.entities = entities;
+        self.frame = Transform::IDENTITY;
+        self.stale = false;
+        self.bounds = self
+            .entities
+            .iter()
+            .filter_map(|x| curves.get(*x).ok())
+            .flat_map(|(_, curve, transform)| {
+                let half_width = curve.brush.width * transform.scale.x.abs() * 0.5;
+                curve.points.iter().map(move |p| {
+                    let p = transform.transform_point(p.extend(0.0)).truncate();
+                    Rect::from_center_half_size(p, Vec2::splat(half_width))
+                })
+            })
+            .reduce(|a, b| a.union(b))
+            .unwrap_or_default();
+    }
+}
+
+/// How a drag of `handle` from `start` to `p` changes a box that was at `frame`.
+fn change(handle: Handle, start: Vec2, p: Vec2, bounds: Rect, frame: &Transform) -> Mat4 {
+    let at = |signs: Vec2| {
+        frame
+            .transform_point((bounds.center() + bounds.half_size() * signs).extend(0.0))
+            .truncate()
+    };
+    let about = |pivot: Vec2, change: Mat4| {
+        Mat4::from_translation(pivot.extend(0.0))
+            * change
+            * Mat4::from_translation(-pivot.extend(0.0))
+    };
+    match handle {
+        Handle::Move => Mat4::from_translation((p - start).extend(0.0)),
+        Handle::Rotate => {
+            let center = at(Vec2::ZERO);
+            let angle = (start - center).angle_to(p - center);
+            about(center, Mat4::from_rotation_z(angle))
+        }
+        Handle::Scale(signs) => {
+            let anchor = at(-signs);
+            let from = start - anchor;
+            let factor = ((p - anchor).dot(from) / from.length_squared()).max(MIN_SCALE);
+            about(anchor, Mat4::from_scale(Vec3::new(factor, factor, 1.0)))
+        }
+    }
+}
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_event::<SelectionEvent>()
+            .add_systems(
+                Update,
+                (
+                    update_selection,
+                    select
+                        .run_if(|tool: Res<Tool>| *tool == Tool::Select)
+                        .run_if(not_replaying),
+                    draw_selection,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Keep the selection to strokes still there, and apply selection events.
+pub(crate) fn update_selection(
+    tool: Res<Tool>,
+    mut events: EventReader<SelectionEvent>,
+    mut history_events: EventReader<HistoryEvent>,
+    mut selection: ResMut<Selection>,
+    curves: Query<(Entity, &Curve, &mut Transform), Without<CurrentCurveMarker>>,
+    mut commands: Commands,
+    mut history: EventWriter<RecordEdit>,
+) {
+    if tool.is_changed() && *tool != Tool::Select {
+        selection.clear();
+    }
+    let gone = selection.entities.iter().any(|x| !curves.contains(*x));
+    if gone || selection.stale {
+        let entities = selection
+            .entities
+            .iter()
+            .copied()
+            .filter(|x| curves.contains(*x))
+            .collect();
+        selection.select(entities, &curves);
+    }
+    // Undo and redo move strokes, which shows once their commands are applied.
+    if history_events.read().count() > 0 && !selection.entities.is_empty() {
+        selection.stale = true;
+    }
+    for event in events.read() {
+        match event {
+            SelectionEvent::Clear => selection.clear(),
+            SelectionEvent::Delete => {
+                let removed: Vec<StrokeRecord> = selection
+                    .entities
+                    .iter()
+                    .filter_map(|x| curves.get(*x).ok())
+                    .map(|(entity, curve, transform)| StrokeRecord {
+                        entity,
+                        curve: curve.clone(),
+                        transform: *transform,
+                    })
+                    .collect();
+                for stroke in &removed {
+                    commands.entity(stroke.entity).despawn();
+                }
+                if !removed.is_empty() {
+                    history.write(RecordEdit(Edit::remove(removed)));
+                }
+                selection.clear();
+            }
+        }
+    }
+}
+
+fn select(
+    pointer: ToolPointer,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform, &Projection)>,
+    mut curves: Query<(Entity, &Curve, &mut Transform), Without<CurrentCurveMarker>>,
+    mut selection: ResMut<Selection>,
+    mut history: EventWriter<RecordEdit>,
+) {
+    let (camera, camera_transform, projection) = *camera;
+    let scale = match projection {
+        Projection::Orthographic(ortho) => ortho.scale,
+        _ => 1.0,
+    };
+    let selection = &mut *selection;
+    let Some(current) = pointer
+        .pressed(selection.drag.is_some())
+        .and_then(|x| camera.viewport_to_world_2d(camera_transform, x).ok())
+    else {
+        // Released.
+        match selection.drag.take() {
+            Some(SelectDrag::Outline { points, rectangle }) => {
+                let entities = outlined(&points, rectangle, scale, &curves);
+                selection.select(entities, &curves);
+            }
+            Some(SelectDrag::Change { strokes, .. }) => {
+                let changed: Vec<(Entity, Transform, Transform)> = strokes
+                    .into_iter()
+                    .filter_map(|(entity, before)| {
+                        let (_, _, after) = curves.get(entity).ok()?;
+                        (*after != before).then_some((entity, before, *after))
+                    })
+                    .collect();
+                if !changed.is_empty() {
+                    history.write(RecordEdit(Edit::Transform(changed)));
+                }
+            }
+            None => {}
+        }
+        return;
+    };
+
+    let Some(drag) = &mut selection.drag else {
+        // Pressed, on a handle or to select anew.
+        selection.drag = Some(match selection.handle_at(current, scale) {
+            Some(handle) => SelectDrag::Change {
+                handle,
+                start: current,
+                frame: selection.frame,
+                strokes: selection
+                    .entities
+                    .iter()
+                    .filter_map(|x| curves.get(*x).ok())
+                    .map(|(entity, _, transform)| (entity, *transform))
+                    .collect(),
+            },
+            None => {
+                selection.entities.clear();
+                SelectDrag::Outline {
+                    points: vec![current],
+                    rectangle: keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+                }
+            }
+        });
+        return;
+    };
+    match drag {
+        SelectDrag::Outline { points, .. } => {
+            if points.last() != Some(&current) {
+                points.push(current);
+            }
+        }
+        SelectDrag::Change {
+            handle,
+            start,
+            frame,
+            strokes,
+        } => {
+            let change = change(*handle, *start, current, selection.bounds, frame);
+            for (entity, before) in strokes.iter() {
+                if let Ok((_, _, mut transform)) = curves.get_mut(*entity) {
+                    *transform = Transform::from_matrix(change * before.compute_matrix());
+                }
+            }
+            selection.frame = Transform::from_matrix(change * frame.compute_matrix());
+        }
+    }
+}
+
+/// Strokes all inside the outline through `points`, or the rectangle between its ends. A tap
+/// picks the stroke under it.
+fn outlined(
+    points: &[Vec2],
+    rectangle: bool,
+    scale: f32,
+    curves: &Query<(Entity, &Curve, &mut Transform), Without<CurrentCurveMarker>>,
+) -> Vec<Entity> {
+    let Some(first) = points.first().copied() else {
+        return vec![];
+    };
+    let last = points.last().copied().unwrap_or(first);
+    if points
+        .iter()
+        .all(|x| x.distance(first) < TAP_DISTANCE * scale)
+    {
+        let radius = HANDLE_SIZE * scale * 0.5;
+        return curves
+            .iter()
+            .find(|(_, curve, transform)| {
+                // Hit test in the space of the curve.
+                let to_local = transform.compute_affine().inverse();
+                let p = to_local.transform_point3(first.extend(0.0)).truncate();
+                let radius = radius / transform.scale.x.abs() + curve.brush.width * 0.5;
+                stroke::hits(curve, p, p, radius)
+            })
+            .map(|x| x.0)
+            .into_iter()
+            .collect();
+    }
+    let outline = if rectangle {
+        let rect = Rect::from_corners(first, last);
+        CORNERS
+            .map(|x| rect.center() + rect.half_size() * x)
+            .to_vec()
+    } else {
+        points.to_vec()
+    };
+    curves
+        .iter()
+        .filter(|(_, curve, transform)| {
+            !curve.points.is_empty()
+                && curve.points.iter().all(|p| {
+                    let p = transform.transform_point(p.extend(0.0)).truncate();
+                    stroke::inside(p, &outline)
+                })
+        })
+        .map(|x| x.0)
+        .collect()
+}
+
+/// Draw the outline being dragged, and the box and handles of the selection.
+fn draw_selection(
+    selection: Res<Selection>,
+    camera: Single<&Projection, With<Camera>>,
+    mut gizmos: Gizmos,
+) {
+    let scale = match *camera {
+        Projection::Orthographic(ortho) => ortho.scale,
+        _ => 1.0,
+    };
+    match &selection.drag {
+        Some(SelectDrag::Outline { points, rectangle }) => {
+            if let (Some(first), Some(last)) = (points.first(), points.last()) {
+                if *rectangle {
+                    let rect = Rect::from_corners(*first, *last);
+                    gizmos.rect_2d(rect.center(), rect.size(), COLOR);
+                } else {
+                    gizmos.linestrip_2d(points.iter().copied(), COLOR);
+                    gizmos.line_2d(*last, *first, COLOR.with_alpha(0.4));
+                }
+            }
+        }
+        Some(SelectDrag::Change { .. }) | None => {}
+    }
+    if selection.entities.is_empty() {
+        return;
+    }
+    let corners = CORNERS.map(|x| selection.at(x));
+    gizmos.linestrip_2d(corners.iter().chain(&corners[..1]).copied(), COLOR);
+    let angle = selection.frame.rotation.to_euler(EulerRot::ZYX).0;
+    for corner in corners {
+        gizmos.rect_2d(
+            Isometry2d::new(corner, Rot2::radians(angle)),
+            Vec2::splat(HANDLE_SIZE * scale),
+            COLOR,
+        );
+    }
+    let knob = selection.knob(scale);
+    gizmos.line_2d(selection.at(Vec2::Y), knob, COLOR);
+    gizmos.circle_2d(knob, HANDLE_SIZE * scale * 0.5, COLOR);
+}
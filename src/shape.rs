@@ -0,0 +1,651 @@
+//! Shapes recognized from rough strokes.
+//!
+//! Strokes drawn with [`Tool::Shapes`], or held still at their end with any pen, are matched
+//! against lines, arrows, ellipses and polygons. When one fits, the stroke becomes a [`Curve`]
+//! carrying the [`ShapeKind`], which is drawn from its parameters with straight edges and sharp
+//! corners rather than along a spline. Its points trace the outline, so hit testing, bounds and
+//! erasing work as for any stroke. Projects keep shapes as [`Elements::Shape`].
+//!
+//! [`Tool::Shapes`]: crate::Tool::Shapes
+//! [`Elements::Shape`]: crate::storage::Elements::Shape
+
+use std::{f32::consts::PI, time::Duration};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CurrentCurveMarker, Curve, IncomingPoints,
+    ink::PenBrush,
+    merge::{Id, Stamp},
+    stroke::point_segment_distance,
+};
+
+/// How long the pointer rests at the end of a stroke to have it recognized with any pen.
+pub const HOLD_TIME: Duration = Duration::from_millis(500);
+/// How far the pointer may shake while resting, in logical pixels.
+const HOLD_RADIUS: f32 = 4.0;
+/// Smallest stroke to recognize, in logical pixels.
+const MIN_SIZE: f32 = 16.0;
+/// Points taken evenly along a stroke to fit shapes to.
+const SAMPLES: usize = 96;
+/// Angles this close to horizontal or vertical snap to it, in radians.
+const SNAP_ANGLE: f32 = 5.0 * PI / 180.0;
+/// Corners turning less than this are taken as straight, in radians.
+const MIN_TURN: f32 = 20.0 * PI / 180.0;
+/// Miters longer than this many half widths are cut short, so sharp corners do not spike.
+const MITER_LIMIT: f32 = 4.0;
+
+/// Geometry of a shape, in canvas units.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Reflect)]
+pub enum ShapeKind {
+    Line {
+        from: Vec2,
+        to: Vec2,
+    },
+    /// A line with a head at `to`.
+    Arrow {
+        from: Vec2,
+        to: Vec2,
+    },
+    /// Turned `rotation` radians counterclockwise about its center.
+    Ellipse {
+        center: Vec2,
+        radii: Vec2,
+        rotation: f32,
+    },
+    /// Turned `rotation` radians counterclockwise about its center.
+    Rectangle {
+        center: Vec2,
+        size: Vec2,
+        rotation: f32,
+    },
+    /// A closed polygon through the corners, such as a triangle.
+    Polygon(Vec<Vec2>),
+    /// An open polyline, left of shapes partly erased.
+    Polyline(Vec<Vec2>),
+}
+
+/// A shape as kept in projects.
+#[derive(Clone, Debug, Serialize, Deserialize, Reflect)]
+pub struct Shape {
+    pub kind: ShapeKind,
+    pub brush: PenBrush,
+    /// Pressure the shape is drawn with, in `0..=1`.
+    pub pressure: f32,
+    #[serde(default = "Id::new")]
+    pub id: Id,
+    #[serde(default)]
+    pub stamp: Stamp,
+    /// Milliseconds since 1970 when drawn, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    /// Milliseconds taken to draw, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+}
+
+impl Shape {
+    /// The shape `curve` is, if any.
+    pub fn of(curve: &Curve) -> Option<Self> {
+        Some(Self {
+            kind: curve.shape.clone()?,
+            brush: curve.brush.clone(),
+            pressure: curve.pressure_at(0),
+            id: curve.id,
+            stamp: curve.stamp,
+            created: curve.created,
+            duration: curve.times.last().copied(),
+        })
+    }
+
+    /// A curve drawn as this shape, with points along its outline.
+    pub fn to_curve(&self) -> Curve {
+        let points = self.kind.outline(self.width());
+        let mut curve = Curve::with_capacity(points.len());
+        curve.which = points.len().saturating_sub(1);
+        curve.pressure = vec![self.pressure; points.len()];
+        // The outline is taken to be drawn at an even pace.
+        curve.times = match self.duration {
+            Some(duration) => (0..points.len() as u64)
+                .map(|i| (u64::from(duration) * i / curve.which.max(1) as u64) as u32)
+                .collect(),
+            None => vec![],
+        };
+        curve.points = points;
+        curve.brush = self.brush.clone();
+        curve.shape = Some(self.kind.clone());
+        curve.id = self.id;
+        curve.stamp = self.stamp;
+        curve.created = self.created;
+        curve
+    }
+
+    fn width(&self) -> f32 {
+        self.brush.width_at(self.pressure)
+    }
+}
+
+impl ShapeKind {
+    /// Lines the shape is drawn with, each with whether it closes into a loop. Curved edges are
+    /// split finely enough for `detail` pixels per canvas unit.
+    pub fn paths(&self, width: f32, detail: f32) -> Vec<(Vec<Vec2>, bool)> {
+        match self {
+            ShapeKind::Line { from, to } => vec![(vec![*from, *to], false)],
+            ShapeKind::Arrow { from, to } => {
+                let shaft = *to - *from;
+                let length = (shaft.length() * 0.3).min(width * 4.0 + 16.0);
+                let back = -shaft.normalize_or_zero() * length;
+                let barb = |angle: f32| *to + Vec2::from_angle(angle).rotate(back);
+                vec![
+                    (vec![*from, *to], false),
+                    (vec![barb(PI / 6.0), *to, barb(-PI / 6.0)], false),
+                ]
+            }
+            ShapeKind::Ellipse {
+                center,
+                radii,
+                rotation,
+            } => {
+                // Segments short enough to stay within a quarter pixel of the ellipse.
+                let radius = radii.max_element().max(f32::EPSILON);
+                let step = 2.0 * (1.0 - 0.25 / (detail * radius)).clamp(-1.0, 1.0).acos();
+                let n = (2.0 * PI / step).ceil().clamp(8.0, 512.0) as usize;
+                let turn = Vec2::from_angle(*rotation);
+                let points = (0..n)
+                    .map(|i| {
+                        let t = 2.0 * PI * i as f32 / n as f32;
+                        *center + turn.rotate(Vec2::new(t.cos(), t.sin()) * *radii)
+                    })
+                    .collect();
+                vec![(points, true)]
+            }
+            ShapeKind::Rectangle {
+                center,
+                size,
+                rotation,
+            } => {
+                let turn = Vec2::from_angle(*rotation);
+                let half = *size * 0.5;
+                let points = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .map(|(x, y)| *center + turn.rotate(half * Vec2::new(x, y)))
+                    .to_vec();
+                vec![(points, true)]
+            }
+            ShapeKind::Polygon(points) => vec![(points.clone(), true)],
+            ShapeKind::Polyline(points) => vec![(points.clone(), false)],
+        }
+    }
+
+    /// The shape moved, turned and scaled by `transform`, which keeps proportions.
+    pub fn transformed(&self, transform: &Transform) -> ShapeKind {
+        let map = |p: &Vec2| transform.transform_point(p.extend(0.0)).truncate();
+        let angle = transform.rotation.to_euler(EulerRot::ZYX).0;
+        let scale = transform.scale.x.abs();
+        match self {
+            ShapeKind::Line { from, to } => ShapeKind::Line {
+                from: map(from),
+                to: map(to),
+            },
+            ShapeKind::Arrow { from, to } => ShapeKind::Arrow {
+                from: map(from),
+                to: map(to),
+            },
+            ShapeKind::Ellipse {
+                center,
+                radii,
+                rotation,
+            } => ShapeKind::Ellipse {
+                center: map(center),
+                radii: *radii * scale,
+                rotation: rotation + angle,
+            },
+            ShapeKind::Rectangle {
+                center,
+                size,
+                rotation,
+            } => ShapeKind::Rectangle {
+                center: map(center),
+                size: *size * scale,
+                rotation: rotation + angle,
+            },
+            ShapeKind::Polygon(points) => ShapeKind::Polygon(points.iter().map(map).collect()),
+            ShapeKind::Polyline(points) => ShapeKind::Polyline(points.iter().map(map).collect()),
+        }
+    }
+
+    /// Points along all lines of the shape, loops closed, as one polyline.
+    fn outline(&self, width: f32) -> Vec<Vec2> {
+        let mut out = vec![];
+        for (mut points, closed) in self.paths(width, 1.0) {
+            if closed && let Some(first) = points.first() {
+                points.push(*first);
+            }
+            out.extend(points);
+        }
+        out
+    }
+
+    /// Mesh of the lines of the shape, `width` wide, with mitered corners.
+    pub(crate) fn mesh(&self, width: f32, detail: f32) -> Mesh {
+        let mut positions: Vec<[f32; 3]> = vec![];
+        let mut indices: Vec<u32> = vec![];
+        let half_width = width * 0.5;
+        for (points, closed) in self.paths(width, detail) {
+            let n = points.len();
+            if n < 2 {
+                continue;
+            }
+            let first = positions.len() as u32;
+            for i in 0..n {
+                let at = |j: usize| points[j % n];
+                let (prev, next) = match (closed, i) {
+                    (true, _) => (at(i + n - 1), at(i + 1)),
+                    (false, 0) => (at(0), at(1)),
+                    (false, _) if i == n - 1 => (at(i - 1), at(i)),
+                    (false, _) => (at(i - 1), at(i + 1)),
+                };
+                let before = (points[i] - prev).normalize_or_zero();
+                let after = (next - points[i]).normalize_or_zero();
+                let (before, after) = match (before == Vec2::ZERO, after == Vec2::ZERO) {
+                    (true, _) => (after, after),
+                    (_, true) => (before, before),
+                    _ => (before, after),
+                };
+                let normal = before.perp();
+                let miter = (before.perp() + after.perp()).normalize_or(normal);
+                let length = (half_width / miter.dot(normal).max(f32::EPSILON))
+                    .min(half_width * MITER_LIMIT);
+                let [left, right] = [points[i] + miter * length, points[i] - miter * length];
+                positions.push([left.x, left.y, 0.0]);
+                positions.push([right.x, right.y, 0.0]);
+            }
+            let pairs = if closed { n } else { n - 1 };
+            for i in 0..pairs as u32 {
+                let a = first + 2 * i;
+                let b = first + 2 * ((i + 1) % n as u32);
+                indices.extend([a, a + 1, b, a + 1, b + 1, b]);
+            }
+        }
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_indices(Indices::U32(indices));
+        mesh
+    }
+}
+
+/// Where and when the pointer last moved while drawing a stroke.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct LastMoved {
+    pub position: Vec2,
+    pub at: Duration,
+}
+
+/// Note when the pointer drawing a stroke moves, to tell whether it rests at the end. Moves
+/// within [`HOLD_RADIUS`] of the last one are taken as the hand shaking.
+pub(crate) fn track_hold(
+    time: Res<Time<Real>>,
+    camera: Single<&Projection, With<Camera>>,
+    mut commands: Commands,
+    strokes: Query<(Entity, &IncomingPoints, Option<&LastMoved>), With<CurrentCurveMarker>>,
+) {
+    let radius = match *camera {
+        Projection::Orthographic(ortho) => HOLD_RADIUS * ortho.scale,
+        _ => HOLD_RADIUS,
+    };
+    for (entity, incoming, moved) in strokes.iter() {
+        let Some(point) = incoming.points.last() else {
+            continue;
+        };
+        if moved.is_none_or(|x| x.position.distance(point.position) > radius) {
+            commands.entity(entity).insert(LastMoved {
+                position: point.position,
+                at: time.elapsed(),
+            });
+        }
+    }
+}
+
+/// The shape `points` were drawn as, if any. `scale` is canvas units per logical pixel, which
+/// sizes are measured against.
+pub fn recognize(points: &[Vec2], scale: f32) -> Option<ShapeKind> {
+    let bounds = points
+        .iter()
+        .fold(Rect::EMPTY, |bounds, p| bounds.union_point(*p));
+    let size = bounds.size().length();
+    if points.len() < 2 || size < MIN_SIZE * scale {
+        return None;
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+    if first.distance(last) < size * 0.2 {
+        closed_shape(&resample(points), size)
+    } else {
+        open_shape(points, size)
+    }
+}
+
+/// A line, or an arrow drawn in one go: the shaft, one barb, back to the tip and the other barb.
+fn open_shape(points: &[Vec2], size: f32) -> Option<ShapeKind> {
+    let corners = corners(points, size * 0.06);
+    match corners[..] {
+        [from, to] => {
+            let to = from + snap(to - from);
+            Some(ShapeKind::Line { from, to })
+        }
+        [from, to, barb, tip, other] => {
+            let shaft = from - to;
+            let head = |barb: Vec2| {
+                let barb = barb - to;
+                barb.length() < shaft.length() * 0.6
+                    && barb.length() > shaft.length() * 0.05
+                    && barb.angle_to(shaft).abs() < 70.0 * PI / 180.0
+            };
+            let opposite = shaft.perp_dot(barb - to) * shaft.perp_dot(other - to) < 0.0;
+            (tip.distance(to) < shaft.length() * 0.25 && head(barb) && head(other) && opposite)
+                .then(|| ShapeKind::Arrow {
+                    from,
+                    to: from + snap(to - from),
+                })
+        }
+        _ => None,
+    }
+}
+
+/// An ellipse or a polygon, whichever fits the loop `samples` better, if either fits well.
+fn closed_shape(samples: &[Vec2], size: f32) -> Option<ShapeKind> {
+    let polygon = polygon(samples, size);
+    let polygon_error = mean(samples.iter().map(|p| {
+        (0..polygon.len())
+            .map(|i| point_segment_distance(*p, polygon[i], polygon[(i + 1) % polygon.len()]))
+            .fold(f32::INFINITY, f32::min)
+    })) / size;
+    let (center, radii, rotation) = fit_ellipse(samples);
+    let turn = Vec2::from_angle(-rotation);
+    let ellipse_error = mean(samples.iter().map(|p| {
+        // Off the ellipse along the ray from its center, roughly.
+        let local = turn.rotate(*p - center);
+        let r = (local / radii).length();
+        if r > 0.0 {
+            (r - 1.0).abs() * local.length() / r
+        } else {
+            radii.min_element()
+        }
+    })) / size;
+
+    if (3..=8).contains(&polygon.len())
+        && polygon_error < 0.03
+        && polygon_error * 2.0 < ellipse_error
+    {
+        return Some(rectangle(&polygon).unwrap_or(ShapeKind::Polygon(polygon)));
+    }
+    (ellipse_error < 0.04).then(|| {
+        let (radii, rotation) = if radii.min_element() > radii.max_element() * 0.9 {
+            (Vec2::splat((radii.x + radii.y) * 0.5), 0.0)
+        } else {
+            (radii, snap_angle(rotation))
+        };
+        ShapeKind::Ellipse {
+            center,
+            radii,
+            rotation,
+        }
+    })
+}
+
+/// Corners of the loop `samples`, with nearly straight ones dropped.
+fn polygon(samples: &[Vec2], size: f32) -> Vec<Vec2> {
+    // Cut the loop at the point farthest from the start, and find corners of both halves.
+    let Some(far) = (0..samples.len()).max_by(|a, b| {
+        let d = |i: usize| samples[i].distance_squared(samples[0]);
+        d(*a).total_cmp(&d(*b))
+    }) else {
+        return vec![];
+    };
+    let epsilon = size * 0.05;
+    let mut out = corners(&samples[..=far], epsilon);
+    out.pop();
+    let back: Vec<Vec2> = samples[far..]
+        .iter()
+        .chain(&samples[..1])
+        .copied()
+        .collect();
+    out.extend(corners(&back, epsilon));
+    out.pop();
+
+    loop {
+        let n = out.len();
+        if n <= 3 {
+            break;
+        }
+        let turn = |i: usize| {
+            let (prev, p, next) = (out[(i + n - 1) % n], out[i], out[(i + 1) % n]);
+            if p.distance(prev) < size * 0.05 {
+                return 0.0;
+            }
+            (p - prev).angle_to(next - p).abs()
+        };
+        let Some((i, angle)) = (0..n)
+            .map(|i| (i, turn(i)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            break;
+        };
+        if angle >= MIN_TURN {
+            break;
+        }
+        out.remove(i);
+    }
+    out
+}
+
+/// A rectangle through `corners` if they are four at right angles.
+fn rectangle(corners: &[Vec2]) -> Option<ShapeKind> {
+    let [a, b, c, d] = corners[..] else {
+        return None;
+    };
+    let corners = [a, b, c, d];
+    let square = (0..4).all(|i| {
+        let (prev, p, next) = (corners[(i + 3) % 4], corners[i], corners[(i + 1) % 4]);
+        ((p - prev).angle_to(next - p).abs() - PI / 2.0).abs() < 15.0 * PI / 180.0
+    });
+    if !square {
+        return None;
+    }
+    // Edges vote for the rotation by length, on angles modulo a right angle.
+    let vote = (0..4)
+        .map(|i| corners[(i + 1) % 4] - corners[i])
+        .map(|edge| Vec2::from_angle(edge.to_angle() * 4.0) * edge.length())
+        .sum::<Vec2>();
+    let rotation = snap_angle(vote.to_angle() / 4.0);
+    let center = corners.iter().sum::<Vec2>() / 4.0;
+    let turn = Vec2::from_angle(-rotation);
+    let size = corners
+        .iter()
+        .map(|p| turn.rotate(*p - center).abs())
+        .sum::<Vec2>()
+        * 0.5;
+    Some(ShapeKind::Rectangle {
+        center,
+        size,
+        rotation,
+    })
+}
+
+/// Center, radii and rotation of the ellipse with the same spread as the loop `samples`.
+fn fit_ellipse(samples: &[Vec2]) -> (Vec2, Vec2, f32) {
+    let center = samples.iter().sum::<Vec2>() / samples.len() as f32;
+    let [xx, yy, xy] = samples
+        .iter()
+        .map(|p| {
+            let d = *p - center;
+            [d.x * d.x, d.y * d.y, d.x * d.y]
+        })
+        .fold([0.0; 3], |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2]])
+        .map(|x| x / samples.len() as f32);
+    let rotation = 0.5 * (2.0 * xy).atan2(xx - yy);
+    // Radii by least squares on `u² / a² + v² / b² = 1`, linear in `1 / a²` and `1 / b²`.
+    let turn = Vec2::from_angle(-rotation);
+    let [uu, uv, vv, u, v] = samples
+        .iter()
+        .map(|p| {
+            let d = turn.rotate(*p - center);
+            let (u, v) = (d.x * d.x, d.y * d.y);
+            [u * u, u * v, v * v, u, v]
+        })
+        .fold([0.0; 5], |a, b| std::array::from_fn(|i| a[i] + b[i]));
+    let det = uu * vv - uv * uv;
+    let inverse = Vec2::new(vv * u - uv * v, uu * v - uv * u) / det;
+    let radii = if det.abs() > f32::EPSILON && inverse.min_element() > 0.0 {
+        Vec2::ONE / Vec2::new(inverse.x.sqrt(), inverse.y.sqrt())
+    } else {
+        // Points spread evenly around an ellipse vary by half the square of each radius.
+        let variance = Vec2::new(
+            xx + yy + (xx - yy).hypot(2.0 * xy),
+            xx + yy - (xx - yy).hypot(2.0 * xy),
+        ) * 0.5;
+        (variance.max(Vec2::ZERO) * 2.0).map(f32::sqrt)
+    };
+    (center, radii, rotation)
+}
+
+/// [`SAMPLES`] points spaced evenly along the loop through `points`.
+fn resample(points: &[Vec2]) -> Vec<Vec2> {
+    let path: Vec<Vec2> = points.iter().chain(&points[..1]).copied().collect();
+    let length: f32 = path.windows(2).map(|x| x[0].distance(x[1])).sum();
+    let step = length / SAMPLES as f32;
+    let mut out = Vec::with_capacity(SAMPLES);
+    let mut travelled = 0.0;
+    for pair in path.windows(2) {
+        let segment = pair[0].distance(pair[1]);
+        while out.len() < SAMPLES && travelled + segment >= step * out.len() as f32 {
+            let t = if segment > 0.0 {
+                (step * out.len() as f32 - travelled) / segment
+            } else {
+                0.0
+            };
+            out.push(pair[0].lerp(pair[1], t));
+        }
+        travelled += segment;
+    }
+    out
+}
+
+/// Corners of the polyline through `points`, by Ramer-Douglas-Peucker with `epsilon`.
+fn corners(points: &[Vec2], epsilon: f32) -> Vec<Vec2> {
+    let n = points.len();
+    if n < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; n];
+    keep[0] = true;
+    keep[n - 1] = true;
+    let mut spans = vec![(0, n - 1)];
+    while let Some((a, b)) = spans.pop() {
+        let Some((distance, i)) = (a + 1..b)
+            .map(|i| (point_segment_distance(points[i], points[a], points[b]), i))
+            .max_by(|x, y| x.0.total_cmp(&y.0))
+        else {
+            continue;
+        };
+        if distance > epsilon {
+            keep[i] = true;
+            spans.push((a, i));
+            spans.push((i, b));
+        }
+    }
+    (0..n).filter(|i| keep[*i]).map(|i| points[i]).collect()
+}
+
+/// `direction` turned onto the nearest axis if it is that close.
+fn snap(direction: Vec2) -> Vec2 {
+    Vec2::from_angle(snap_angle(direction.to_angle())) * direction.length()
+}
+
+/// `angle` rounded to a right angle if it is that close.
+fn snap_angle(angle: f32) -> f32 {
+    let right = (angle / (PI / 2.0)).round() * (PI / 2.0);
+    if (angle - right).abs() < SNAP_ANGLE {
+        right
+    } else {
+        angle
+    }
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), x| (sum + x, count + 1));
+    if count == 0 { 0.0 } else { sum / count as f32 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `n` points around an ellipse, back to the first.
+    fn ellipse(center: Vec2, radii: Vec2, n: usize) -> Vec<Vec2> {
+        (0..=n)
+            .map(|i| center + radii * Vec2::from_angle(2.0 * PI * i as f32 / n as f32))
+            .collect()
+    }
+
+    #[test]
+    fn recognizes_circles() {
+        let Some(ShapeKind::Ellipse { center, radii, .. }) =
+            recognize(&ellipse(Vec2::new(10.0, 20.0), Vec2::splat(50.0), 64), 1.0)
+        else {
+            panic!("circle not recognized");
+        };
+        assert!(center.distance(Vec2::new(10.0, 20.0)) < 1.0);
+        assert!((radii - Vec2::splat(50.0)).abs().max_element() < 1.0);
+        assert_eq!(radii.x, radii.y);
+    }
+
+    #[test]
+    fn recognizes_rectangles_and_lines() {
+        let square = [
+            [0.0, 0.0],
+            [100.0, 0.0],
+            [100.0, 60.0],
+            [0.0, 60.0],
+            [0.0, 1.0],
+        ];
+        let square: Vec<Vec2> = square.into_iter().map(Vec2::from).collect();
+        assert!(matches!(
+            recognize(&square, 1.0),
+            Some(ShapeKind::Rectangle { .. })
+        ));
+        let line: Vec<Vec2> = (0..20).map(|i| Vec2::new(i as f32 * 5.0, 1.0)).collect();
+        assert!(matches!(
+            recognize(&line, 1.0),
+            Some(ShapeKind::Line { from, to }) if from.y == to.y
+        ));
+    }
+
+    #[test]
+    fn rejects_scribbles() {
+        // Back and forth across, with uneven strokes, and closed up.
+        let mut scribble: Vec<Vec2> = (0..40)
+            .map(|i| {
+                let x = if i % 2 == 0 {
+                    0.0
+                } else {
+                    80.0 + (i * 7 % 13) as f32
+                };
+                Vec2::new(x, i as f32 * 3.0 + (i * 5 % 11) as f32)
+            })
+            .collect();
+        scribble.push(scribble[0] + Vec2::ONE);
+        assert_eq!(recognize(&scribble, 1.0), None);
+        let open: Vec<Vec2> = scribble[..20].to_vec();
+        assert_eq!(recognize(&open, 1.0), None);
+        let tiny = ellipse(Vec2::ZERO, Vec2::splat(4.0), 32);
+        assert_eq!(recognize(&tiny, 1.0), None);
+        assert!(recognize(&tiny, 0.1).is_some());
+    }
+}
@@ -0,0 +1,158 @@
+//! Stroke stabilizer, smoothing pointer input before splines are formed.
+//!
+//! Ink is pulled along by the pointer on a string of [`Tuning::stabilizer`] length, like a lazy
+//! brush: the pointer moves freely within the string, so jitter shorter than it never reaches the
+//! stroke, and corners get rounded off. The string is measured on screen, so it feels the same at
+//! any zoom.
+
+use bevy::prelude::*;
+
+use crate::{Curve, IncomingPoints, StrokeFinished, args::Tuning, ink::StrokePoint};
+
+/// State of the stabilizer for a stroke being drawn.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Stabilizer {
+    /// Where the pointer is, which the ink trails.
+    pointer: Option<StrokePoint>,
+}
+
+/// Replace the raw points of strokes being drawn with stabilized ones. Finished strokes catch up
+/// with the pointer, so they end where it was lifted.
+pub(crate) fn stabilize(
+    tuning: Res<Tuning>,
+    camera: Single<&Projection, With<Camera>>,
+    mut finished: EventReader<StrokeFinished>,
+    mut strokes: Query<(&Curve, &mut IncomingPoints, &mut Stabilizer)>,
+) {
+    if tuning.stabilizer <= 0.0 {
+        finished.clear();
+        return;
+    }
+    let scale = match *camera {
+        Projection::Orthographic(ortho) => ortho.scale,
+        _ => 1.0,
+    };
+    let string = tuning.stabilizer * scale;
+    for (curve, mut incoming, mut stabilizer) in strokes.iter_mut() {
+        if incoming.points.is_empty() {
+            continue;
+        }
+        let Some(mut ink) = curve.points.last().copied() else {
+            continue;
+        };
+        let mut out = vec![];
+        for point in std::mem::take(&mut incoming.points) {
+            let offset = point.position - ink;
+            let length = offset.length();
+            if length > string {
+                ink += offset * (1.0 - string / length);
+                // The ink moves when the pointer pulls it.
+                out.push(StrokePoint {
+                    position: ink,
+                    ..point
+                });
+            }
+            stabilizer.pointer = Some(point);
+        }
+        incoming.points = out;
+    }
+    for StrokeFinished(entity) in finished.read() {
+        let Ok((curve, mut incoming, mut stabilizer)) = strokes.get_mut(*entity) else {
+            continue;
+        };
+        let ink = incoming
+            .points
+            .last()
+            .map(|x| x.position)
+            .or(curve.points.last().copied());
+        if let Some(pointer) = stabilizer.pointer.take()
+            && ink != Some(pointer.position)
+        {
+            incoming.points.push(pointer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// String length, in canvas units at the default zoom.
+    const STRING: f32 = 10.0;
+
+    /// An app stabilizing a stroke that starts at the origin.
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_event::<StrokeFinished>()
+            .insert_resource(Tuning {
+                stabilizer: STRING,
+                ..Default::default()
+            })
+            .add_systems(Update, stabilize);
+        app.world_mut().spawn((
+            Camera::default(),
+            Projection::Orthographic(OrthographicProjection::default_2d()),
+        ));
+        let curve = Curve {
+            points: vec![Vec2::ZERO],
+            ..Default::default()
+        };
+        let stroke = app
+            .world_mut()
+            .spawn((
+                curve,
+                IncomingPoints { points: vec![] },
+                Stabilizer::default(),
+            ))
+            .id();
+        (app, stroke)
+    }
+
+    /// Feed `points` to `stroke`, and return what comes out, added to the stroke.
+    fn feed(app: &mut App, stroke: Entity, points: &[Vec2]) -> Vec<StrokePoint> {
+        let mut entity = app.world_mut().entity_mut(stroke);
+        entity.get_mut::<IncomingPoints>().unwrap().points =
+            points.iter().map(|x| StrokePoint::new(*x, 1.0)).collect();
+        app.update();
+        let mut entity = app.world_mut().entity_mut(stroke);
+        let out = std::mem::take(&mut entity.get_mut::<IncomingPoints>().unwrap().points);
+        let mut curve = entity.get_mut::<Curve>().unwrap();
+        curve.points.extend(out.iter().map(|x| x.position));
+        out
+    }
+
+    #[test]
+    fn jitter_is_ignored() {
+        let (mut app, stroke) = app();
+        let jitter = [
+            vec2(3.0, 0.0),
+            vec2(-2.0, 4.0),
+            vec2(0.0, -9.0),
+            vec2(7.0, 7.0),
+        ];
+        assert!(feed(&mut app, stroke, &jitter).is_empty());
+    }
+
+    #[test]
+    fn ink_trails_the_pointer() {
+        let (mut app, stroke) = app();
+        let out = feed(&mut app, stroke, &[vec2(25.0, 0.0)]);
+        assert_eq!(out.len(), 1);
+        assert!(out[0].position.distance(vec2(15.0, 0.0)) < 1e-4);
+
+        // Further points are pulled from where the ink is, here diagonally.
+        let out = feed(&mut app, stroke, &[vec2(20.0, 0.0), vec2(15.0, 20.0)]);
+        assert_eq!(out.len(), 1);
+        assert!(out[0].position.distance(vec2(15.0, 10.0)) < 1e-4);
+    }
+
+    #[test]
+    fn finished_strokes_catch_up() {
+        let (mut app, stroke) = app();
+        feed(&mut app, stroke, &[vec2(25.0, 0.0), vec2(26.0, 3.0)]);
+        app.world_mut().send_event(StrokeFinished(stroke));
+        let out = feed(&mut app, stroke, &[]);
+        assert_eq!(out.last().map(|x| x.position), Some(vec2(26.0, 3.0)));
+    }
+}
@@ -1,24 +1,43 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
 
 use bevy::{
     app::{App, Plugin},
-    asset::AssetApp,
     ecs::{
         event::{Event, EventReader, EventWriter},
         query::With,
         system::{Commands, Query, Res, ResMut},
     },
-    log::{debug, info},
+    log::{info, warn},
     prelude::*,
     reflect::Reflect,
-    state::state::States,
-    tasks::Task,
+    tasks::{AsyncComputeTaskPool, Task, block_on, poll_once},
 };
 #[cfg(feature = "storage")]
 use bevy_pkv::PkvStore;
 use serde::{Deserialize, Serialize};
 
-use crate::{Curve, ui::OverlayEvent};
+use crate::{
+    CurrentCurveMarker, Curve,
+    args::Tuning,
+    format::{self, Encoding},
+    history::History,
+    merge::{Id, Stamp},
+    pages::Notebook,
+    shape::Shape,
+    spawn_curve,
+    ui::OverlayEvent,
+};
+
+/// Name of the main canvas.
+pub const MAIN_CANVAS: &str = ".main";
+
+/// Key of the saved project in the key-value store.
+#[cfg(feature = "storage")]
+const PROJECT_KEY: &str = "project";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Project {
@@ -28,6 +47,9 @@ pub struct Project {
     /// Canvas. Main canvas has name of `.main`.
     /// Any name begin with `.` is reserved for internal use.
     pub canvas: HashMap<String, Canvas>,
+    /// Order of canvases as pages, by name.
+    #[serde(default)]
+    pub pages: Vec<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Reflect)]
@@ -37,33 +59,322 @@ pub struct ProjectInfo {
     pub date: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Reflect)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Reflect)]
 pub struct Canvas {
     pub strokes: Vec<Curve>,
     pub elements: Vec<Elements>,
+    /// Strokes and elements removed, and when, so merges leave them out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub removed: BTreeMap<Id, Stamp>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Reflect)]
 pub enum Elements {
     Curve(Curve),
-    Peek(String),
-    // TODO
-    Shape(),
+    Peek {
+        text: String,
+        #[serde(default = "Id::new")]
+        id: Id,
+        /// Milliseconds since 1970 when added, if known.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        created: Option<u64>,
+    },
+    Shape(Shape),
+}
+
+impl Elements {
+    /// Identity of the element, kept across saves.
+    pub fn id(&self) -> Id {
+        match self {
+            Elements::Curve(curve) => curve.id(),
+            Elements::Peek { id, .. } => *id,
+            Elements::Shape(shape) => shape.id,
+        }
+    }
+
+    /// Milliseconds since 1970 when the element was made, if known.
+    pub fn created(&self) -> Option<u64> {
+        match self {
+            Elements::Curve(curve) => curve.created(),
+            Elements::Peek { created, .. } => *created,
+            Elements::Shape(shape) => shape.created,
+        }
+    }
+}
+
+impl Canvas {
+    /// A canvas of `curves` after `elements`, curves drawn as shapes kept as [`Elements::Shape`].
+    pub fn new(curves: Vec<Curve>, mut elements: Vec<Elements>) -> Self {
+        let mut strokes = vec![];
+        for curve in curves {
+            match Shape::of(&curve) {
+                Some(shape) => elements.push(Elements::Shape(shape)),
+                None => strokes.push(curve),
+            }
+        }
+        Self {
+            strokes,
+            elements,
+            removed: BTreeMap::new(),
+        }
+    }
+
+    /// All strokes, including those among elements and shapes.
+    pub fn curves(&self) -> impl Iterator<Item = Cow<'_, Curve>> {
+        self.strokes
+            .iter()
+            .map(Cow::Borrowed)
+            .chain(self.elements.iter().filter_map(|x| match x {
+                Elements::Curve(curve) => Some(Cow::Borrowed(curve)),
+                Elements::Shape(shape) => Some(Cow::Owned(shape.to_curve())),
+                Elements::Peek { .. } => None,
+            }))
+    }
+
+    /// Take the strokes and shapes out as curves, to spawn as entities.
+    pub fn take_curves(&mut self) -> Vec<Curve> {
+        let mut curves = std::mem::take(&mut self.strokes);
+        self.elements.retain(|x| match x {
+            Elements::Shape(shape) => {
+                curves.push(shape.to_curve());
+                false
+            }
+            _ => true,
+        });
+        curves
+    }
 }
 
+impl Project {
+    /// A project holding only the main canvas.
+    pub fn new(title: impl Into<String>, main: Canvas) -> Self {
+        Self {
+            title: title.into(),
+            info: ProjectInfo::now(),
+            canvas: HashMap::from([(MAIN_CANVAS.to_owned(), main)]),
+            pages: vec![MAIN_CANVAS.to_owned()],
+        }
+    }
+
+    /// Canvases in page order. Canvases missing from the order follow, the main one first, then
+    /// the others by name.
+    pub fn pages(&self) -> Vec<(&str, &Canvas)> {
+        let mut pages: Vec<(&str, &Canvas)> =
+            self.canvas.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        pages.sort_by_key(|(name, _)| {
+            (
+                self.pages
+                    .iter()
+                    .position(|x| x == name)
+                    .unwrap_or(usize::MAX),
+                *name != MAIN_CANVAS,
+                *name,
+            )
+        });
+        pages
+    }
+}
+
+impl ProjectInfo {
+    /// Project info stamped with current time and app version.
+    pub fn now() -> Self {
+        let date = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        Self {
+            author: String::new(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            date: date.to_string(),
+        }
+    }
+}
+
+/// Requests to storage.
+#[derive(Event, Debug, Clone)]
+pub enum StorageEvent {
+    Save,
+    Load,
+}
+
+/// File of the opened project. Without one, the project lives in the key-value store.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ProjectPath(pub Option<PathBuf>);
+
 #[derive(Component)]
 struct LoadTask(Task<Result<Project, String>>);
 
+/// Encoded project. Files are written on the task, the key-value store when it finishes.
 #[derive(Component)]
-struct SaveTask(Task<Result<(), String>>);
+struct SaveTask(Task<Result<Vec<u8>, String>>);
 
-#[cfg(feature = "storage")]
-pub fn do_save(
-    curves: Query<&Curve>,
-    overlay_event: EventWriter<OverlayEvent>,
-    pkv: Res<PkvStore>,
+pub struct StoragePlugin;
+
+impl Plugin for StoragePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StorageEvent>()
+            .init_resource::<ProjectPath>()
+            .add_systems(Startup, |mut events: EventWriter<StorageEvent>| {
+                events.write(StorageEvent::Load);
+            })
+            .add_systems(
+                Update,
+                (do_save, do_load, poll_save_task, poll_load_task).chain(),
+            );
+        #[cfg(feature = "storage")]
+        app.insert_resource(PkvStore::new("metawrite", "metawrite"));
+    }
+}
+
+fn do_save(
+    mut commands: Commands,
+    mut events: EventReader<StorageEvent>,
+    curves: Query<(&Curve, &Transform), Without<CurrentCurveMarker>>,
+    tasks: Query<(), With<SaveTask>>,
+    mut overlay_event: EventWriter<OverlayEvent>,
+    path: Res<ProjectPath>,
+    mut notebook: ResMut<Notebook>,
 ) {
+    if !events.read().any(|x| matches!(x, StorageEvent::Save)) {
+        return;
+    }
+    if !tasks.is_empty() {
+        info!("Save already in progress");
+        return;
+    }
+    if path.0.is_none() && cfg!(not(feature = "storage")) {
+        warn!("No project file to save to");
+        return;
+    }
+    // Strokes are saved as they are shown, with their transforms baked in.
+    let strokes: Vec<Curve> = curves
+        .iter()
+        .map(|(curve, transform)| curve.transformed(transform))
+        .collect();
+    let active = notebook.active().to_owned();
+    notebook.store_removed(&active, &strokes);
+    let project = notebook.project("Untitled", strokes);
+    let path = path.0.clone();
+    overlay_event.write(OverlayEvent::Overlay("Saving...".to_owned()));
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let encoding = path.as_deref().map(Encoding::for_path).unwrap_or_default();
+        let data = format::encode(&project, encoding).map_err(|e| e.to_string())?;
+        if let Some(path) = path {
+            std::fs::write(&path, &data).map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+        Ok(data)
+    });
+    commands.spawn(SaveTask(task));
 }
 
-#[cfg(feature = "storage")]
-pub fn do_load(commands: Commands, pkv: Res<PkvStore>) {}
+fn do_load(
+    mut commands: Commands,
+    mut events: EventReader<StorageEvent>,
+    tasks: Query<(), With<LoadTask>>,
+    mut overlay_event: EventWriter<OverlayEvent>,
+    path: Res<ProjectPath>,
+    #[cfg(feature = "storage")] pkv: Res<PkvStore>,
+) {
+    if !events.read().any(|x| matches!(x, StorageEvent::Load)) {
+        return;
+    }
+    if !tasks.is_empty() {
+        return;
+    }
+    let task = if let Some(path) = path.0.clone() {
+        AsyncComputeTaskPool::get().spawn(async move {
+            format::load_file(&path).map_err(|e| format!("{}: {e}", path.display()))
+        })
+    } else {
+        #[cfg(feature = "storage")]
+        {
+            // Early builds stored bare JSON as a string.
+            let Some(data) = pkv
+                .get::<Vec<u8>>(PROJECT_KEY)
+                .or_else(|_| pkv.get::<String>(PROJECT_KEY).map(String::into_bytes))
+                .ok()
+            else {
+                info!("No saved project");
+                return;
+            };
+            AsyncComputeTaskPool::get()
+                .spawn(async move { format::decode(&data).map_err(|e| e.to_string()) })
+        }
+        #[cfg(not(feature = "storage"))]
+        return;
+    };
+    overlay_event.write(OverlayEvent::Overlay("Loading...".to_owned()));
+    commands.spawn(LoadTask(task));
+}
+
+fn poll_save_task(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut SaveTask)>,
+    mut overlay_event: EventWriter<OverlayEvent>,
+    path: Res<ProjectPath>,
+    #[cfg(feature = "storage")] mut pkv: ResMut<PkvStore>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(result) = block_on(poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        overlay_event.write(OverlayEvent::Normal);
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to save project: {e}");
+                continue;
+            }
+        };
+        if path.0.is_none() {
+            #[cfg(feature = "storage")]
+            if let Err(e) = pkv.set(PROJECT_KEY, &data) {
+                warn!("Failed to save project: {e}");
+                continue;
+            }
+        }
+        info!("Project saved, {} bytes", data.len());
+    }
+}
+
+/// Replace current pages with loaded ones, showing the first.
+fn poll_load_task(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut LoadTask)>,
+    curves: Query<Entity, With<Curve>>,
+    mut overlay_event: EventWriter<OverlayEvent>,
+    mut history: ResMut<History>,
+    mut notebook: ResMut<Notebook>,
+    tuning: Res<Tuning>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        // Without async loading, strokes show up in the first frame.
+        let result = if tuning.async_assets {
+            block_on(poll_once(&mut task.0))
+        } else {
+            Some(block_on(&mut task.0))
+        };
+        let Some(result) = result else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        overlay_event.write(OverlayEvent::Normal);
+        let project = match result {
+            Ok(project) => project,
+            Err(e) => {
+                warn!("Failed to load project: {e}");
+                continue;
+            }
+        };
+        for curve in curves.iter() {
+            commands.entity(curve).despawn();
+        }
+        history.clear();
+        *notebook = Notebook::from_project(project);
+        info!("Loaded {} pages", notebook.pages().len());
+        for curve in notebook.take_active_strokes() {
+            spawn_curve(&mut commands, curve);
+        }
+    }
+}
@@ -1 +1,369 @@
+//! Geometry on the points of strokes.
 
+use std::ops::Range;
+
+use bevy::{math::Vec2, transform::components::Transform};
+
+use crate::{
+    Curve, CyclingMode,
+    merge::{Id, Stamp},
+    shape::ShapeKind,
+};
+
+/// Distance from `p` to the segment `a`-`b`.
+pub fn point_segment_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0.0 {
+        ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    p.distance(a + ab * t)
+}
+
+/// Distance between segments `a0`-`a1` and `b0`-`b1`.
+pub fn segment_distance(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> f32 {
+    let d = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+    let crossing = d(a0, a1, b0) * d(a0, a1, b1) < 0.0 && d(b0, b1, a0) * d(b0, b1, a1) < 0.0;
+    if crossing {
+        return 0.0;
+    }
+    point_segment_distance(a0, b0, b1)
+        .min(point_segment_distance(a1, b0, b1))
+        .min(point_segment_distance(b0, a0, a1))
+        .min(point_segment_distance(b1, a0, a1))
+}
+
+/// Whether the polyline through the points of `curve` comes within `radius` of the segment
+/// `from`-`to`. Loops are closed from their last point back to the first.
+pub fn hits(curve: &Curve, from: Vec2, to: Vec2, radius: f32) -> bool {
+    let points = &curve.points;
+    let closing = match points[..] {
+        [first, .., last] if curve.closed() => Some([last, first]),
+        _ => None,
+    };
+    match points[..] {
+        [] => false,
+        [p] => point_segment_distance(p, from, to) <= radius,
+        _ => points
+            .windows(2)
+            .chain(closing.as_ref().map(|x| &x[..]))
+            .any(|x| segment_distance(x[0], x[1], from, to) <= radius),
+    }
+}
+
+/// Whether `p` is inside the polygon through `outline`, by the even-odd rule.
+pub fn inside(p: Vec2, outline: &[Vec2]) -> bool {
+    let mut inside = false;
+    for (i, a) in outline.iter().enumerate() {
+        let b = outline[(i + 1) % outline.len()];
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (b.x - a.x) * (p.y - a.y) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl Curve {
+    /// Part of the curve with points in `range`, as a new stroke. Parts of loops are open, and
+    /// parts of shapes are polylines.
+    pub(crate) fn slice(&self, range: Range<usize>) -> Curve {
+        self.pick(range.collect())
+    }
+
+    /// The points at `indices`, in that order, as a new stroke like [`Curve::slice`] makes.
+    fn pick(&self, indices: Vec<usize>) -> Curve {
+        let points: Vec<Vec2> = indices.iter().map(|&i| self.points[i]).collect();
+        Curve {
+            which: points.len().saturating_sub(1),
+            pressure: indices.iter().map(|&i| self.pressure_at(i)).collect(),
+            brush: self.brush.clone(),
+            spline: self.spline,
+            cycling: CyclingMode::NotCyclic,
+            shape: self
+                .shape
+                .as_ref()
+                .map(|_| ShapeKind::Polyline(points.clone())),
+            points,
+            id: Id::new(),
+            stamp: Stamp::now(),
+            created: self.created,
+            times: self.times_of(indices),
+        }
+    }
+
+    /// The curve with its first point again at the end, so a loop is walked all the way round.
+    fn unrolled(&self) -> Curve {
+        let mut out = self.clone();
+        if let Some(&first) = self.points.first() {
+            out.points.push(first);
+            out.pressure = (0..out.points.len())
+                .map(|i| self.pressure_at(i % self.points.len()))
+                .collect();
+            if self.times.len() == self.points.len() {
+                out.times.push(self.times[0]);
+            }
+            out.which = out.points.len() - 1;
+        }
+        out
+    }
+
+    /// Drop points the curve through the others passes within `tolerance` of, by
+    /// Ramer-Douglas-Peucker. Pressure counts as the change in width it makes at the point.
+    pub(crate) fn simplify(&self, tolerance: f32) -> Curve {
+        let n = self.points.len();
+        if n < 3 || tolerance <= 0.0 {
+            return self.clone();
+        }
+        let mut keep = vec![false; n];
+        keep[0] = true;
+        keep[n - 1] = true;
+        let mut spans = vec![(0, n - 1)];
+        while let Some((a, b)) = spans.pop() {
+            let Some((error, i)) = (a + 1..b)
+                .map(|i| (self.deviation(i, a, b), i))
+                .max_by(|x, y| x.0.total_cmp(&y.0))
+            else {
+                continue;
+            };
+            if error > tolerance {
+                keep[i] = true;
+                spans.push((a, i));
+                spans.push((i, b));
+            }
+        }
+        let kept: Vec<usize> = (0..n).filter(|i| keep[*i]).collect();
+        Curve {
+            points: kept.iter().map(|i| self.points[*i]).collect(),
+            which: kept.len() - 1,
+            pressure: kept.iter().map(|i| self.pressure_at(*i)).collect(),
+            brush: self.brush.clone(),
+            spline: self.spline,
+            cycling: self.cycling,
+            shape: self.shape.clone(),
+            id: self.id,
+            stamp: self.stamp,
+            created: self.created,
+            times: self.times_of(kept.iter().copied()),
+        }
+    }
+
+    /// Times of the points at `indices`, none if the curve has no times.
+    fn times_of(&self, indices: impl IntoIterator<Item = usize>) -> Vec<u32> {
+        if self.times.len() != self.points.len() {
+            return vec![];
+        }
+        indices.into_iter().map(|i| self.times[i]).collect()
+    }
+
+    /// How far point `i` is from the segment between points `a` and `b`, in position or half
+    /// width.
+    fn deviation(&self, i: usize, a: usize, b: usize) -> f32 {
+        let (pa, pb, p) = (self.points[a], self.points[b], self.points[i]);
+        let ab = pb - pa;
+        let t = if ab.length_squared() > 0.0 {
+            ((p - pa).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let pressure = self.pressure_at(a) + (self.pressure_at(b) - self.pressure_at(a)) * t;
+        let width = self.brush.width_at(self.pressure_at(i)) - self.brush.width_at(pressure);
+        p.distance(pa + ab * t).max(width.abs() * 0.5)
+    }
+
+    /// The curve as `transform` shows it, its points and width in canvas units. Transforms are
+    /// taken to keep proportions, as the selection tool makes them.
+    pub(crate) fn transformed(&self, transform: &Transform) -> Curve {
+        if *transform == Transform::IDENTITY {
+            return self.clone();
+        }
+        let mut out = self.clone();
+        for p in out.points.iter_mut() {
+            *p = transform.transform_point(p.extend(0.0)).truncate();
+        }
+        out.brush.width *= transform.scale.x.abs();
+        out.shape = self.shape.as_ref().map(|x| x.transformed(transform));
+        out
+    }
+
+    /// Insert points so no two neighbours are farther apart than `spacing`. Shapes keep their
+    /// geometry, the new points being on their edges.
+    pub(crate) fn densify(&self, spacing: f32) -> Curve {
+        let mut out = Curve {
+            brush: self.brush.clone(),
+            spline: self.spline,
+            cycling: self.cycling,
+            shape: self.shape.clone(),
+            id: self.id,
+            stamp: self.stamp,
+            created: self.created,
+            ..Default::default()
+        };
+        let timed = self.times.len() == self.points.len();
+        for i in 0..self.points.len() {
+            if i > 0 {
+                let (a, b) = (self.points[i - 1], self.points[i]);
+                let (pa, pb) = (self.pressure_at(i - 1), self.pressure_at(i));
+                let steps = (a.distance(b) / spacing).ceil() as usize;
+                for step in 1..steps {
+                    let t = step as f32 / steps as f32;
+                    out.points.push(a.lerp(b, t));
+                    out.pressure.push(pa + (pb - pa) * t);
+                    if timed {
+                        let (ta, tb) = (self.times[i - 1] as f32, self.times[i] as f32);
+                        out.times.push((ta + (tb - ta) * t) as u32);
+                    }
+                }
+            }
+            out.points.push(self.points[i]);
+            out.pressure.push(self.pressure_at(i));
+            if timed {
+                out.times.push(self.times[i]);
+            }
+        }
+        out.which = out.points.len().saturating_sub(1);
+        out
+    }
+}
+
+/// Cut away the parts of `curve` within `radius` of the segment `from`-`to`.
+///
+/// Returns `None` if the curve is untouched, otherwise the pieces left, which may be none.
+pub fn erase(curve: &Curve, from: Vec2, to: Vec2, radius: f32) -> Option<Vec<Curve>> {
+    if !hits(curve, from, to, radius) {
+        return None;
+    }
+    let closed = curve.closed();
+    let curve = if closed {
+        curve.unrolled().densify(radius * 0.5)
+    } else {
+        curve.densify(radius * 0.5)
+    };
+    let mut pieces = vec![];
+    let mut start = None;
+    for (i, p) in curve.points.iter().enumerate() {
+        let inside = point_segment_distance(*p, from, to) <= radius;
+        match (start, inside) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                pieces.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let n = curve.points.len();
+    if let Some(s) = start {
+        pieces.push(s..n);
+    }
+    let mut pieces: Vec<Vec<usize>> = pieces.into_iter().map(Iterator::collect).collect();
+    // On a loop, the pieces either side of its first point are one piece. The last point of the
+    // unrolled loop is the first again.
+    if closed
+        && pieces.len() > 1
+        && pieces[0].first() == Some(&0)
+        && pieces.last().and_then(|x| x.last()) == Some(&(n - 1))
+    {
+        let first = pieces.remove(0);
+        if let Some(last) = pieces.last_mut() {
+            last.pop();
+            last.extend(first);
+        }
+    }
+    Some(
+        pieces
+            .into_iter()
+            .filter(|x| x.len() >= 2)
+            .map(|x| curve.pick(x))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[[f32; 2]]) -> Curve {
+        Curve {
+            points: points.iter().map(|x| Vec2::from(*x)).collect(),
+            which: points.len() - 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn simplify_drops_collinear_points() {
+        let line = curve(&[[0.0, 0.0], [1.0, 0.0], [2.0, 0.01], [3.0, 0.0], [4.0, 0.0]]);
+        let simple = line.simplify(0.1);
+        assert_eq!(simple.points, [Vec2::new(0.0, 0.0), Vec2::new(4.0, 0.0)]);
+        assert_eq!(simple.which, 1);
+        assert_eq!(simple.id, line.id);
+    }
+
+    #[test]
+    fn simplify_keeps_corners_and_ends() {
+        let mut corner = curve(&[[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [2.0, 1.0], [2.0, 2.0]]);
+        corner.times = vec![0, 10, 20, 30, 40];
+        let simple = corner.simplify(0.1);
+        assert_eq!(
+            simple.points,
+            [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(2.0, 0.0),
+                Vec2::new(2.0, 2.0)
+            ]
+        );
+        assert_eq!(simple.times, [0, 20, 40]);
+        assert_eq!(simple.pressure.len(), 3);
+    }
+
+    #[test]
+    fn simplify_keeps_changes_in_width() {
+        let mut line = curve(&[[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]]);
+        line.pressure = vec![1.0, 0.2, 1.0];
+        assert_eq!(line.simplify(0.1).points.len(), 3);
+        line.pressure = vec![1.0, 1.0, 1.0];
+        assert_eq!(line.simplify(0.1).points.len(), 2);
+    }
+
+    #[test]
+    fn erase_cuts_through() {
+        let line = curve(&[[0.0, 0.0], [10.0, 0.0]]);
+        let pieces = erase(&line, Vec2::new(5.0, -5.0), Vec2::new(5.0, 5.0), 1.0).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].points[0], Vec2::ZERO);
+        assert_eq!(*pieces[1].points.last().unwrap(), Vec2::new(10.0, 0.0));
+        assert!(
+            pieces
+                .iter()
+                .all(|x| x.points.iter().all(|p| (p.x - 5.0).abs() > 1.0))
+        );
+        assert!(erase(&line, Vec2::new(5.0, 3.0), Vec2::new(6.0, 3.0), 1.0).is_none());
+    }
+
+    #[test]
+    fn erase_cuts_loops_at_the_seam() {
+        let mut square = curve(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]]);
+        let (from, to) = (Vec2::new(-5.0, 5.0), Vec2::new(5.0, 5.0));
+        // Only the segment closing the loop passes there.
+        assert!(erase(&square, from, to, 1.0).is_none());
+        square.cycling = CyclingMode::Cyclic;
+        let pieces = erase(&square, from, to, 1.0).unwrap();
+        assert_eq!(pieces.len(), 1);
+        let piece = &pieces[0];
+        assert_eq!(piece.cycling, CyclingMode::NotCyclic);
+        assert_eq!(piece.pressure.len(), piece.points.len());
+        let (first, last) = (piece.points[0], *piece.points.last().unwrap());
+        assert!(first.x == 0.0 && first.y < 4.0);
+        assert!(last.x == 0.0 && last.y > 6.0);
+        for corner in square.points {
+            assert!(piece.points.contains(&corner));
+        }
+        assert!(
+            piece
+                .points
+                .iter()
+                .all(|p| p.distance(Vec2::new(0.0, 5.0)) > 1.0)
+        );
+    }
+}
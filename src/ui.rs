@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use bevy::{
     app::{App, Plugin},
     ecs::{
@@ -11,14 +13,33 @@ use bevy::{
     state::state::{NextState, State, States},
 };
 
+use crate::{Tool, ink::ActiveBrush};
+
+/// Ink colors offered by the palette, in sRGB.
+const PALETTE_COLORS: [Vec4; 7] = [
+    Vec4::new(1.0, 1.0, 1.0, 1.0),
+    Vec4::new(0.95, 0.26, 0.21, 1.0),
+    Vec4::new(1.0, 0.6, 0.0, 1.0),
+    Vec4::new(1.0, 0.92, 0.23, 1.0),
+    Vec4::new(0.3, 0.69, 0.31, 1.0),
+    Vec4::new(0.13, 0.59, 0.95, 1.0),
+    Vec4::new(0.61, 0.15, 0.69, 1.0),
+];
+const PALETTE_WIDTHS: [f32; 4] = [1.5, 3.0, 6.0, 12.0];
+const PALETTE_OPACITIES: [f32; 3] = [1.0, 0.5, 0.25];
+const SWATCH_SIZE: f32 = 28.0;
+
 #[derive(Event, Debug, Clone)]
 pub enum OverlayEvent {
     Normal,
     Overlay(String),
-    /// TODO: Message goes off after some time.
+    /// Message that goes off after [`TRANSIENT_TIME`], without blocking input.
     Transient(String),
 }
 
+/// How long a transient message is shown.
+pub const TRANSIENT_TIME: Duration = Duration::from_secs(3);
+
 #[derive(States, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum OverlayState {
     Normal,
@@ -28,11 +49,17 @@ pub enum OverlayState {
 #[derive(Component, Debug, Clone)]
 pub struct OverlayMarker;
 
+/// A transient message, and the time left until it goes off.
+#[derive(Component, Debug, Clone)]
+pub struct TransientMarker(Timer);
+
 pub struct OverlayPlugin;
 
 impl Plugin for OverlayPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (draw_overlay,));
+        app.add_event::<OverlayEvent>()
+            .insert_state(OverlayState::Normal)
+            .add_systems(Update, (draw_overlay, expire_transient).chain());
     }
 }
 
@@ -42,11 +69,15 @@ fn draw_overlay(
     mut events: EventReader<OverlayEvent>,
     mut next_state: ResMut<NextState<OverlayState>>,
     overlay: Query<Entity, With<OverlayMarker>>,
+    transient: Query<Entity, With<TransientMarker>>,
 ) {
-    overlay
-        .iter()
-        .for_each(|entity| commands.entity(entity).despawn());
     for i in events.read() {
+        // Only one overlay is shown at a time.
+        if matches!(i, OverlayEvent::Normal | OverlayEvent::Overlay(_)) {
+            overlay
+                .iter()
+                .for_each(|entity| commands.entity(entity).despawn());
+        }
         match i {
             OverlayEvent::Normal => {
                 *next_state = NextState::Pending(OverlayState::Normal);
@@ -84,7 +115,7 @@ fn draw_overlay(
                             ))
                             .with_children(|parent| {
                                 parent.spawn((
-                                    Text::new("Loading..."),
+                                    Text::new(msg),
                                     TextFont {
                                         font_size: 24.0,
                                         ..default()
@@ -94,7 +125,307 @@ fn draw_overlay(
                             });
                     });
             }
-            _ => {}
+            OverlayEvent::Transient(msg) => {
+                // A newer message replaces the one shown.
+                transient
+                    .iter()
+                    .for_each(|entity| commands.entity(entity).despawn());
+                commands
+                    .spawn((
+                        TransientMarker(Timer::new(TRANSIENT_TIME, TimerMode::Once)),
+                        Node {
+                            position_type: PositionType::Absolute,
+                            width: Val::Percent(100.),
+                            bottom: Val::Px(72.),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                    ))
+                    .with_children(|parent| {
+                        parent
+                            .spawn((
+                                Node {
+                                    padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                                    ..default()
+                                },
+                                BackgroundColor(Color::srgba(0.2, 0.2, 0.2, 0.9)),
+                                BorderRadius::all(Val::Px(6.0)),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    Text::new(msg),
+                                    TextFont {
+                                        font_size: 16.0,
+                                        ..default()
+                                    },
+                                    TextColor(Color::WHITE),
+                                ));
+                            });
+                    });
+            }
+        }
+    }
+}
+
+/// Take transient messages off once their time is up.
+fn expire_transient(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut transient: Query<(Entity, &mut TransientMarker)>,
+) {
+    for (entity, mut marker) in transient.iter_mut() {
+        if marker.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// A button of the brush palette, setting one property of the active brush.
+#[derive(Component, Debug, Clone, Copy)]
+pub enum PaletteButton {
+    Color(Vec4),
+    Width(f32),
+    Opacity(f32),
+}
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveBrush>()
+            .add_systems(Startup, spawn_palette)
+            .add_systems(Update, (handle_palette, update_palette).chain());
+    }
+}
+
+fn spawn_palette(mut commands: Commands) {
+    let swatch = Node {
+        width: Val::Px(SWATCH_SIZE),
+        height: Val::Px(SWATCH_SIZE),
+        border: UiRect::all(Val::Px(2.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(24.),
+            right: Val::Px(24.),
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(8.0),
+            ..default()
+        })
+        .with_children(|parent| {
+            for color in PALETTE_COLORS {
+                parent.spawn((
+                    Button,
+                    PaletteButton::Color(color),
+                    swatch.clone(),
+                    BackgroundColor(Color::srgba(color.x, color.y, color.z, color.w)),
+                    BorderColor(Color::NONE),
+                    BorderRadius::MAX,
+                ));
+            }
+            for width in PALETTE_WIDTHS {
+                parent
+                    .spawn((
+                        Button,
+                        PaletteButton::Width(width),
+                        swatch.clone(),
+                        BorderColor(Color::NONE),
+                        BorderRadius::all(Val::Px(4.0)),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Node {
+                                width: Val::Px(width),
+                                height: Val::Px(width),
+                                ..default()
+                            },
+                            BackgroundColor(Color::WHITE),
+                            BorderRadius::MAX,
+                        ));
+                    });
+            }
+            for opacity in PALETTE_OPACITIES {
+                parent
+                    .spawn((
+                        Button,
+                        PaletteButton::Opacity(opacity),
+                        Node {
+                            width: Val::Auto,
+                            padding: UiRect::horizontal(Val::Px(4.0)),
+                            ..swatch.clone()
+                        },
+                        BorderColor(Color::NONE),
+                        BorderRadius::all(Val::Px(4.0)),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Text::new(format!("{}%", (opacity * 100.0) as u32)),
+                            TextFont {
+                                font_size: 14.0,
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn handle_palette(
+    buttons: Query<(&Interaction, &PaletteButton), Changed<Interaction>>,
+    mut brush: ResMut<ActiveBrush>,
+) {
+    for (interaction, button) in buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *button {
+            PaletteButton::Color(color) => brush.0.color = color,
+            PaletteButton::Width(width) => brush.0.width = width,
+            PaletteButton::Opacity(opacity) => brush.0.opacity = opacity,
+        }
+    }
+}
+
+/// Outline the buttons matching the active brush.
+fn update_palette(brush: Res<ActiveBrush>, mut buttons: Query<(&PaletteButton, &mut BorderColor)>) {
+    if !brush.is_changed() {
+        return;
+    }
+    for (button, mut border) in buttons.iter_mut() {
+        let selected = match *button {
+            PaletteButton::Color(color) => brush.0.color == color,
+            PaletteButton::Width(width) => brush.0.width == width,
+            PaletteButton::Opacity(opacity) => brush.0.opacity == opacity,
+        };
+        border.0 = if selected {
+            Color::srgb(0.8, 0.8, 0.8)
+        } else {
+            Color::NONE
+        };
+    }
+}
+
+/// A button switching to a tool.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ToolButton(pub Tool);
+
+pub struct ToolbarPlugin;
+
+impl Plugin for ToolbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_toolbar)
+            .add_systems(Update, (handle_toolbar, update_toolbar).chain());
+    }
+}
+
+fn spawn_toolbar(mut commands: Commands) {
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            right: Val::Px(12.),
+            flex_direction: FlexDirection::Row,
+            column_gap: Val::Px(8.0),
+            ..default()
+        })
+        .with_children(|parent| {
+            for (tool, label) in [
+                (Tool::Pen, "pen"),
+                (Tool::Shapes, "shapes"),
+                (Tool::StrokeEraser, "eraser"),
+                (Tool::PartialEraser, "part eraser"),
+                (Tool::Select, "select"),
+            ] {
+                parent
+                    .spawn((
+                        Button,
+                        ToolButton(tool),
+                        Node {
+                            padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                            border: UiRect::all(Val::Px(2.0)),
+                            ..default()
+                        },
+                        BorderColor(Color::NONE),
+                        BorderRadius::all(Val::Px(4.0)),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((Text::new(label),));
+                    });
+            }
+        });
+}
+
+fn handle_toolbar(
+    buttons: Query<(&Interaction, &ToolButton), Changed<Interaction>>,
+    mut tool: ResMut<Tool>,
+) {
+    for (interaction, button) in buttons {
+        if *interaction == Interaction::Pressed {
+            *tool = button.0;
+        }
+    }
+}
+
+/// Outline the button of the current tool.
+fn update_toolbar(tool: Res<Tool>, mut buttons: Query<(&ToolButton, &mut BorderColor)>) {
+    if !tool.is_changed() {
+        return;
+    }
+    for (button, mut border) in buttons.iter_mut() {
+        border.0 = if button.0 == *tool {
+            Color::srgb(0.8, 0.8, 0.8)
+        } else {
+            Color::NONE
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{state::app::StatesPlugin, time::TimeUpdateStrategy};
+
+    use super::*;
+
+    /// Time between updates, under the largest step virtual time takes.
+    const STEP: Duration = Duration::from_millis(200);
+
+    fn shown(app: &mut App) -> usize {
+        app.world_mut()
+            .query_filtered::<(), With<TransientMarker>>()
+            .iter(app.world())
+            .count()
+    }
+
+    #[test]
+    fn transient_messages_go_off() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, OverlayPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(STEP));
+        app.world_mut()
+            .send_event(OverlayEvent::Transient("one".into()));
+        app.update();
+        app.world_mut()
+            .send_event(OverlayEvent::Transient("two".into()));
+        app.update();
+        assert_eq!(shown(&mut app), 1);
+        assert_eq!(
+            *app.world().resource::<State<OverlayState>>().get(),
+            OverlayState::Normal
+        );
+        // The second message is shown for its full time, despite the first one being older.
+        let steps = TRANSIENT_TIME.as_millis() / STEP.as_millis();
+        for _ in 2..steps {
+            app.update();
+        }
+        assert_eq!(shown(&mut app), 1);
+        for _ in 0..3 {
+            app.update();
         }
+        assert_eq!(shown(&mut app), 0);
     }
 }